@@ -0,0 +1,345 @@
+use lazy_static::lazy_static;
+use log::debug;
+use rustdns::{Record, Resource, Type};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Longest time a negative answer is cached for, regardless of the TTL given by the dns server
+/// ([RFC 2308 section 5](https://datatracker.ietf.org/doc/html/rfc2308#section-5))
+const MAX_NEGATIVE_TTL: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// How long a SERVFAIL from a dns server is cached for, which must be at most 5 minutes
+/// ([RFC 2308 section 7.1](https://datatracker.ietf.org/doc/html/rfc2308#section-7.1))
+const SERVER_FAILURE_TTL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// Cache shared by every lookup, so records are only requested again once their TTL has passed
+    pub(crate) static ref DNS_CACHE: Mutex<DnsCache> = Mutex::new(DnsCache::default());
+}
+
+/// Answer to a single question, as stored in the cache
+#[derive(Debug, Clone)]
+pub(crate) enum Answer {
+    /// Records returned by the dns server, which are empty if the name exists without any records of the type asked for
+    Records(Vec<Record>),
+    /// The name doesn't exist
+    NxDomain,
+}
+
+/// Answer for a single question, along with when it stops being valid
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    /// Answer given by the dns server
+    answer: Answer,
+    /// Time at which the answer expires, based on the lowest TTL of all records, or the negative TTL for negative answers
+    expires: Instant,
+}
+
+/// Stores answers to dns questions until their TTLs expire
+#[derive(Debug, Default)]
+pub(crate) struct DnsCache {
+    /// Answers from each dns server, which are cached separately as servers can disagree, such as when a split
+    /// horizon server has records another doesn't
+    entries: HashMap<(SocketAddr, String, Type), CacheEntry>,
+    /// Questions each dns server has responded to with SERVFAIL, and when to try asking them again
+    server_failures: HashMap<(SocketAddr, String, Type), Instant>,
+}
+
+impl DnsCache {
+    /// Returns the cached answer from the given dns server for the given name and record type, if it's still valid.
+    ///
+    /// TTLs of the returned records are adjusted to show how long they have left before expiry
+    pub(crate) fn get(
+        &mut self,
+        server: SocketAddr,
+        name: &str,
+        record_type: Type,
+    ) -> Option<Answer> {
+        let entry = unexpired(
+            &mut self.entries,
+            (server, normalise_name(name), record_type),
+        )?;
+
+        let remaining = entry.expires.saturating_duration_since(Instant::now());
+        let answer = match &entry.answer {
+            Answer::Records(records) => Answer::Records(
+                records
+                    .iter()
+                    .cloned()
+                    .map(|mut record| {
+                        record.ttl = record.ttl.min(remaining);
+                        record
+                    })
+                    .collect(),
+            ),
+            Answer::NxDomain => Answer::NxDomain,
+        };
+
+        Some(answer)
+    }
+
+    /// Caches records from a dns server for the given name and record type, expiring once the lowest TTL has passed.
+    ///
+    /// Empty answers are not cached
+    pub(crate) fn insert(
+        &mut self,
+        server: SocketAddr,
+        name: &str,
+        record_type: Type,
+        records: Vec<Record>,
+    ) {
+        let Some(ttl) = records.iter().map(|record| record.ttl).min() else {
+            return;
+        };
+
+        if ttl == Duration::ZERO {
+            return;
+        }
+
+        self.entries.insert(
+            (server, normalise_name(name), record_type),
+            CacheEntry {
+                answer: Answer::Records(records),
+                expires: Instant::now() + ttl,
+            },
+        );
+    }
+
+    /// Caches a negative answer (NXDOMAIN, or a response without any records) from a dns server for the given name
+    /// and record type.
+    ///
+    /// Like records, negative answers are only used for questions sent to the same dns server, so one server that
+    /// can't find a name doesn't stop the others (or the fallback servers) being asked.
+    ///
+    /// The answer is cached for the negative TTL found in the SOA record of the authority section, as described by
+    /// [RFC 2308 section 5](https://datatracker.ietf.org/doc/html/rfc2308#section-5).
+    /// Negative answers without a SOA record are not cached
+    pub(crate) fn insert_negative(
+        &mut self,
+        server: SocketAddr,
+        name: &str,
+        record_type: Type,
+        answer: Answer,
+        authorities: &[Record],
+    ) {
+        let Some(ttl) = negative_ttl(authorities) else {
+            return;
+        };
+
+        if ttl == Duration::ZERO {
+            return;
+        }
+
+        self.entries.insert(
+            (server, normalise_name(name), record_type),
+            CacheEntry {
+                answer,
+                expires: Instant::now() + ttl,
+            },
+        );
+    }
+
+    /// Whether the given dns server recently responded to this question with SERVFAIL, so shouldn't be asked again yet
+    pub(crate) fn has_server_failure(
+        &mut self,
+        server: SocketAddr,
+        name: &str,
+        record_type: Type,
+    ) -> bool {
+        let key = (server, normalise_name(name), record_type);
+        match self.server_failures.get(&key) {
+            Some(expires) if *expires > Instant::now() => true,
+            Some(_) => {
+                self.server_failures.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Remembers that a dns server responded to a question with SERVFAIL.
+    ///
+    /// Failures are cached separately for each dns server, as another server may still be able to answer
+    pub(crate) fn insert_server_failure(
+        &mut self,
+        server: SocketAddr,
+        name: &str,
+        record_type: Type,
+    ) {
+        self.server_failures.insert(
+            (server, normalise_name(name), record_type),
+            Instant::now() + SERVER_FAILURE_TTL,
+        );
+    }
+}
+
+/// Finds the entry with the given key, removing it instead if it has expired
+fn unexpired<K: Eq + Hash + Debug>(
+    entries: &mut HashMap<K, CacheEntry>,
+    key: K,
+) -> Option<&CacheEntry> {
+    if entries.get(&key)?.expires <= Instant::now() {
+        debug!("cached answer for {key:?} has expired");
+        entries.remove(&key);
+        return None;
+    }
+
+    entries.get(&key)
+}
+
+/// Finds how long a negative answer can be cached for, which is the lower of the TTL and minimum field of the SOA record
+fn negative_ttl(authorities: &[Record]) -> Option<Duration> {
+    authorities
+        .iter()
+        .find_map(|record| match &record.resource {
+            Resource::SOA(soa) => Some(record.ttl.min(soa.minimum).min(MAX_NEGATIVE_TTL)),
+            _ => None,
+        })
+}
+
+/// Normalises a domain name so lookups are case insensitive and ignore a trailing dot
+pub(crate) fn normalise_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustdns::{Class, SOA};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    /// Dns server answers are cached from
+    const SERVER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 53);
+
+    fn a_record(ttl: u64) -> Record {
+        Record::new(
+            "example.com.",
+            Class::Internet,
+            Duration::from_secs(ttl),
+            Resource::A(Ipv4Addr::new(1, 2, 3, 4)),
+        )
+    }
+
+    #[test]
+    fn test_cache_hit() {
+        let mut cache = DnsCache::default();
+        cache.insert(SERVER, "Example.com.", Type::A, vec![a_record(60)]);
+
+        let Some(Answer::Records(records)) = cache.get(SERVER, "example.com", Type::A) else {
+            panic!("expected cached records");
+        };
+        assert_eq!(records.len(), 1);
+        assert!(records[0].ttl <= Duration::from_secs(60));
+
+        assert!(cache.get(SERVER, "example.com", Type::AAAA).is_none());
+
+        // records are only used for the dns server that gave them
+        let other_server = "10.0.0.2:53".parse().unwrap();
+        assert!(cache.get(other_server, "example.com", Type::A).is_none());
+    }
+
+    #[test]
+    fn test_cache_expiry() {
+        let mut cache = DnsCache::default();
+        cache.insert(SERVER, "example.com", Type::A, vec![a_record(0)]);
+        assert!(cache.get(SERVER, "example.com", Type::A).is_none());
+
+        cache.insert(SERVER, "example.com", Type::A, vec![a_record(60)]);
+        cache
+            .entries
+            .values_mut()
+            .for_each(|entry| entry.expires = Instant::now());
+        assert!(cache.get(SERVER, "example.com", Type::A).is_none());
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn test_negative_cache() {
+        let soa = |ttl: u64, minimum: u64| {
+            Record::new(
+                "example.com.",
+                Class::Internet,
+                Duration::from_secs(ttl),
+                Resource::SOA(SOA {
+                    mname: "ns.example.com.".to_string(),
+                    rname: "hostmaster@example.com".to_string(),
+                    serial: 1,
+                    refresh: Duration::from_secs(3600),
+                    retry: Duration::from_secs(600),
+                    expire: Duration::from_secs(86400),
+                    minimum: Duration::from_secs(minimum),
+                }),
+            )
+        };
+
+        let mut cache = DnsCache::default();
+
+        // negative answers without a SOA record can't be cached
+        cache.insert_negative(
+            SERVER,
+            "missing.example.com",
+            Type::A,
+            Answer::NxDomain,
+            &[],
+        );
+        assert!(cache.get(SERVER, "missing.example.com", Type::A).is_none());
+
+        cache.insert_negative(
+            SERVER,
+            "missing.example.com",
+            Type::A,
+            Answer::NxDomain,
+            &[soa(3600, 60)],
+        );
+        assert!(matches!(
+            cache.get(SERVER, "missing.example.com", Type::A),
+            Some(Answer::NxDomain)
+        ));
+
+        // negative answers are only used for the dns server that gave them
+        let other_server = "10.0.0.2:53".parse().unwrap();
+        assert!(cache
+            .get(other_server, "missing.example.com", Type::A)
+            .is_none());
+
+        // the lower of the SOA record's TTL and minimum is used
+        cache.insert_negative(
+            SERVER,
+            "example.com",
+            Type::AAAA,
+            Answer::Records(Vec::new()),
+            &[soa(0, 60)],
+        );
+        assert!(cache.get(SERVER, "example.com", Type::AAAA).is_none());
+        assert_eq!(negative_ttl(&[soa(30, 60)]), Some(Duration::from_secs(30)));
+        assert_eq!(negative_ttl(&[soa(86400, 86400)]), Some(MAX_NEGATIVE_TTL));
+    }
+
+    #[test]
+    fn test_server_failure_cache() {
+        let (server, other_server) = (
+            "10.0.0.1:53".parse().unwrap(),
+            "10.0.0.2:53".parse().unwrap(),
+        );
+
+        let mut cache = DnsCache::default();
+        cache.insert_server_failure(server, "Example.com", Type::A);
+
+        assert!(cache.has_server_failure(server, "example.com.", Type::A));
+        assert!(!cache.has_server_failure(server, "example.com", Type::AAAA));
+        assert!(!cache.has_server_failure(other_server, "example.com", Type::A));
+
+        cache
+            .server_failures
+            .values_mut()
+            .for_each(|expires| *expires = Instant::now());
+        assert!(!cache.has_server_failure(server, "example.com", Type::A));
+        assert!(cache.server_failures.is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{
+    transport::{Nameserver, Protocol, StreamConnector, TcpTransport, Transport},
+    Error, Result,
+};
+use log::debug;
+use rustdns::{Extension, Message, Rcode};
+use std::{net::SocketAddr, time::Duration};
+
+/// UDP payload size advertised with EDNS0, as recommended by [DNS flag day 2020](https://www.dnsflagday.net/2020/)
+pub(crate) const EDNS_PAYLOAD_SIZE: u16 = 1232;
+
+/// Connection to a single dns server, which sends questions using the protocol of the dns server.
+/// Questions sent over UDP fall back to TCP when a response is too large to fit in a datagram
+pub(crate) struct Connection {
+    /// Address of the dns server
+    address: SocketAddr,
+    /// Transport used to send questions to the dns server
+    transport: Box<dyn Transport>,
+    /// Transport used to resend questions with truncated responses, if the main transport can truncate them
+    tcp_fallback: Option<TcpTransport>,
+}
+
+impl Connection {
+    /// Creates a new connection to the given dns server
+    pub(crate) async fn new(
+        nameserver: &Nameserver,
+        timeout: Duration,
+        attempts: usize,
+    ) -> Result<Self> {
+        let tcp_fallback = match nameserver.protocol {
+            Protocol::Udp => Some(TcpTransport::new(StreamConnector::tcp(
+                nameserver.address,
+                timeout,
+            ))),
+            _ => None,
+        };
+
+        Ok(Self {
+            address: nameserver.address,
+            transport: nameserver.transport(timeout, attempts).await?,
+            tcp_fallback,
+        })
+    }
+
+    /// Address of the dns server
+    pub(crate) fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Sends a message to the dns server and returns its response.
+    ///
+    /// EDNS0 is advertised so larger responses fit in a single datagram, and is dropped if the server
+    /// doesn't understand it. Truncated responses are retried over TCP
+    pub(crate) async fn exchange(&self, message: &Message) -> Result<Message> {
+        let mut edns_message = message.clone();
+        edns_message.add_extension(Extension {
+            payload_size: EDNS_PAYLOAD_SIZE,
+            ..Default::default()
+        });
+
+        let mut response = self.send(self.transport.as_ref(), &edns_message).await?;
+
+        // servers that don't support EDNS0 are meant to reply with FORMERR, so try again without it
+        if response.rcode == Rcode::FormErr {
+            debug!(
+                "{} doesn't support EDNS0, retrying without it",
+                self.address
+            );
+            response = self.send(self.transport.as_ref(), message).await?;
+        }
+
+        if let (true, Some(tcp_fallback)) = (response.tc, &self.tcp_fallback) {
+            debug!(
+                "response from {} was truncated, retrying over TCP",
+                self.address
+            );
+            response = self.send(tcp_fallback, message).await?;
+        }
+
+        Ok(response)
+    }
+
+    /// Sends a message using the given transport and parses the response.
+    ///
+    /// Every message is given a new random id, so responses to earlier messages can't be mistaken for this one
+    async fn send(&self, transport: &dyn Transport, message: &Message) -> Result<Message> {
+        let mut message = message.clone();
+        message.id = Message::random_id();
+
+        let response = transport.exchange(&message.to_vec()?).await?;
+
+        Message::from_slice(&response).map_err(|e| Error::MalformedResponse {
+            server: self.address,
+            reason: e.to_string(),
+        })
+    }
+}
@@ -1,128 +1,805 @@
-use anyhow::{anyhow, Result};
-use lazy_static::lazy_static;
-use log::{debug, info};
-use rustdns::{Class, Message, Resource, Type};
+use crate::{
+    cache::{normalise_name, Answer, DNS_CACHE},
+    connection::Connection,
+    hosts::lookup_hosts,
+    ip_preference::AddressFamily,
+    srv::order_srv_records,
+    Error, IpPreference, Nameserver, Resolver, Result,
+};
+use log::{debug, info, warn};
+use rustdns::{Class, Message, Rcode, Record, Resource, Type};
 use std::{
-    net::{IpAddr, SocketAddr, UdpSocket},
-    str::FromStr,
+    collections::HashSet, future::Future, net::IpAddr, pin::Pin, str::FromStr, sync::Arc,
     time::Duration,
 };
+use tokio::task::JoinSet;
 
-/// Creates code to add a question for a specific record_type to a given message with a domain
-macro_rules! message_question {
-    ($message:expr, $domain:expr => SRV) => {
-        $message.add_question(
-            &format!("_minecraft._tcp.{}", $domain),
-            Type::SRV,
-            Class::Internet,
-        )
+/// Creates the name to ask a question about for a specific record_type and domain
+macro_rules! question_name {
+    ($domain:expr => SRV) => {
+        format!("_minecraft._tcp.{}", $domain)
     };
-    ($message:expr, $domain:expr => $record_type:ident) => {
-        $message.add_question($domain, Type::$record_type, Class::Internet);
+    ($domain:expr => $record_type:ident) => {
+        $domain.to_string()
     };
 }
 
-/// Performs a DNS request to find the specified record type, using given socket and domain
+/// Performs a DNS request to find the specified record type, using given connection and domain,
+/// returning every matching record along with its TTL
+macro_rules! find_records {
+    ($connection:expr, $domain:expr => $record_type:ident) => {{
+        let name = question_name!($domain => $record_type);
+
+        // once we have the answers, find the ones we care about
+        query($connection, &name, Type::$record_type)
+            .await
+            .map(|answers| {
+                answers
+                    .iter()
+                    .filter_map(|record| {
+                        if let Resource::$record_type(rec) = &record.resource {
+                            Some((rec.clone(), record.ttl))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+    }};
+}
+
+/// Performs a DNS request to find the specified record type, using given connection and domain,
+/// returning the first matching record along with its TTL
 macro_rules! find_record {
-    ($socket:expr, $domain:expr => $record_type:ident) => {{
-        // create requests
-        let mut message = Message::default();
-        message_question!(message, $domain => $record_type);
-
-        debug!("checking {} for {} record", $domain, stringify!($record_type));
-
-        // send over socket
-        let question = message.to_vec()?;
-        $socket.send(&question)?;
-
-        // read into buffer and then parse
-        let mut response = [0; 512];
-        let len = $socket.recv(&mut response)?;
-
-        // now we have the answers, find the ones we care about
-        let answers = Message::from_slice(&response[0..len])?.answers;
-        answers.iter().find_map(|record| {
-            if let Resource::$record_type(rec) = &record.resource {
-                Some(rec.clone())
-            } else {
-                None
+    ($connection:expr, $domain:expr => $record_type:ident) => {
+        find_records!($connection, $domain => $record_type)
+            .map(|records| records.into_iter().next())
+    };
+}
+
+/// Maximum number of names followed during a single lookup before giving up
+const MAX_CHAIN_LENGTH: usize = 16;
+
+/// Address found by a successful lookup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lookup {
+    /// Ip address the domain resolved to
+    pub ip: IpAddr,
+    /// Port to connect to, either from an SRV record or the one originally requested
+    pub port: u16,
+    /// Lowest TTL of all records used during the lookup, after which it should be repeated
+    pub ttl: Duration,
+    /// Every name followed to find the ip, ending with the ip itself
+    /// (such as `_minecraft._tcp.example.com`, `proxy.example.com`, `1.2.3.4`)
+    pub chain: Vec<String>,
+    /// Where the ip came from
+    pub origin: Origin,
+}
+
+/// Where the ip of a [Lookup] came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Origin {
+    /// An A record
+    A,
+    /// An AAAA record
+    Aaaa,
+    /// The target of a SRV or CNAME record, which was an ip rather than a domain
+    Literal,
+    /// An entry in the hosts file
+    HostsFile,
+    /// An override from [Resolver::static_hosts]
+    StaticOverride,
+}
+
+impl Lookup {
+    /// Describes how the domain was resolved, for use in logs
+    pub fn describe_chain(&self) -> String {
+        self.chain.join(" -> ")
+    }
+}
+
+/// Finds answers for a given name and record type, using cached answers if they have not expired.
+///
+/// Negative answers are cached too, as described by [RFC 2308](https://datatracker.ietf.org/doc/html/rfc2308),
+/// so names that don't exist aren't asked about over and over
+async fn query(connection: &Connection, name: &str, record_type: Type) -> Result<Vec<Record>> {
+    let server_failure_error = || Error::ServFail {
+        server: connection.address(),
+        name: name.to_string(),
+    };
+
+    {
+        let mut cache = DNS_CACHE.lock().unwrap();
+        match cache.get(connection.address(), name, record_type) {
+            Some(Answer::Records(answers)) => {
+                debug!("using cached {record_type} records for {name}");
+                return Ok(answers);
             }
-        })
-    }};
+            Some(Answer::NxDomain) => {
+                debug!(
+                    "using cached NXDOMAIN for {name} from {}",
+                    connection.address()
+                );
+                return Err(Error::NxDomain(name.to_string()));
+            }
+            None if cache.has_server_failure(connection.address(), name, record_type) => {
+                debug!(
+                    "using cached SERVFAIL for {name} from {}",
+                    connection.address()
+                );
+                return Err(server_failure_error());
+            }
+            None => {}
+        }
+    }
+
+    // create requests
+    let mut message = Message::default();
+    message.add_question(name, record_type, Class::Internet);
+
+    debug!("checking {name} for {record_type} record");
+
+    let response = connection.exchange(&message).await?;
+    match response.rcode {
+        Rcode::NoError => {}
+        Rcode::NXDomain => {
+            DNS_CACHE.lock().unwrap().insert_negative(
+                connection.address(),
+                name,
+                record_type,
+                Answer::NxDomain,
+                &response.authoritys,
+            );
+            return Err(Error::NxDomain(name.to_string()));
+        }
+        Rcode::ServFail => {
+            DNS_CACHE.lock().unwrap().insert_server_failure(
+                connection.address(),
+                name,
+                record_type,
+            );
+            return Err(server_failure_error());
+        }
+        rcode => {
+            return Err(Error::Rcode {
+                server: connection.address(),
+                name: name.to_string(),
+                rcode,
+            })
+        }
+    }
+
+    let answers = response.answers;
+    if answers.is_empty() {
+        // the name exists, but has no records of this type (NODATA)
+        DNS_CACHE.lock().unwrap().insert_negative(
+            connection.address(),
+            name,
+            record_type,
+            Answer::Records(Vec::new()),
+            &response.authoritys,
+        );
+    } else {
+        DNS_CACHE
+            .lock()
+            .unwrap()
+            .insert(connection.address(), name, record_type, answers.clone());
+    }
+
+    Ok(answers)
 }
 
-/// looks up ip address for a given domain and port, checking SRV, CNAME and A records (in that order)
-/// using a single provided dns server
-fn domain_lookup_individual(domain: &str, port: u16, dns_server: IpAddr) -> Result<(IpAddr, u16)> {
-    // first create a socket for dns requests
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
-    socket.set_read_timeout(Some(Duration::new(5, 0)))?;
-    socket.connect(SocketAddr::new(dns_server, 53))?;
-
-    // inner method to help with recursive search
-    fn domain_lookup_inner(socket: &UdpSocket, domain: &str, port: u16) -> Result<(IpAddr, u16)> {
-        // check for SRV, A and CNAME records (in that order) and use results as discovered
-        let (ip, port) = if let Some(srv) = find_record!(socket, domain => SRV) {
-            info!("using SRV record:\n\t{srv}");
-
-            (srv.name, srv.port)
-        } else if let Some(a) = find_record!(socket, domain => A) {
-            info!("using A record:\n\t{a}");
-
-            (a.to_string(), port)
-        } else if let Some(cname) = find_record!(socket, domain => CNAME) {
-            info!("using CNAME record:\n\t{cname}");
-
-            (cname, port)
+/// Finds every address record for the given domain, with A and AAAA records in the order given by `preference`
+async fn find_addresses(
+    connection: &Connection,
+    domain: &str,
+    preference: IpPreference,
+) -> Result<Vec<(IpAddr, Duration, Origin)>> {
+    let mut addresses = Vec::new();
+    let mut error = None;
+
+    for family in preference.order() {
+        let records = match family {
+            AddressFamily::Ipv4 => find_records!(connection, domain => A).map(|records| {
+                records
+                    .into_iter()
+                    .map(|(a, ttl)| (IpAddr::from(a), ttl, Origin::A))
+                    .collect::<Vec<_>>()
+            }),
+            AddressFamily::Ipv6 => find_records!(connection, domain => AAAA).map(|records| {
+                records
+                    .into_iter()
+                    .map(|(aaaa, ttl)| (IpAddr::from(aaaa), ttl, Origin::Aaaa))
+                    .collect()
+            }),
+        };
+
+        match records {
+            Ok(records) => addresses.extend(records),
+            // the other family won't have any addresses for a name that doesn't exist
+            Err(e @ Error::NxDomain(_)) => return Err(e),
+            // but can still be used if the other failed for any other reason
+            Err(e) => {
+                debug!("failed to find {family:?} addresses for {domain}: {e}");
+                error.get_or_insert(e);
+            }
+        }
+    }
+
+    match error {
+        Some(e) if addresses.is_empty() => Err(e),
+        _ => Ok(addresses),
+    }
+}
+
+/// looks up every address for a given domain and port, checking SRV, A/AAAA and CNAME records (in that order)
+/// using a single provided dns server.
+///
+/// Addresses are ordered by the priority and weight of the SRV records they came from, then by `preference`
+async fn domain_lookup_individual(
+    domain: &str,
+    port: u16,
+    dns_server: &Nameserver,
+    resolver: &Resolver,
+) -> Result<Vec<Lookup>> {
+    // first create a connection for dns requests
+    let config = &resolver.config;
+    let connection = Connection::new(dns_server, config.timeout, config.attempts).await?;
+
+    // inner method which checks for SRV records, which are only used for the domain originally asked for
+    async fn domain_lookup_inner(
+        connection: &Connection,
+        domain: &str,
+        port: u16,
+        resolver: &Resolver,
+    ) -> Result<Vec<Lookup>> {
+        // check for SRV records first, resolving each target in the order given by their priority and weight
+        let srv_records = if resolver.srv_lookup {
+            // most domains have no SRV record, and some servers (such as stubs that don't forward SRV questions)
+            // fail to answer them, so carry on looking for other records either way
+            find_records!(connection, domain => SRV).unwrap_or_else(|e| {
+                debug!("no SRV records for {domain}: {e}");
+                Vec::new()
+            })
         } else {
-            return Err(anyhow!("no valid records"));
+            Vec::new()
         };
 
+        if !srv_records.is_empty() {
+            let srv_records = order_srv_records(srv_records, &mut rand::thread_rng());
+
+            // targets that fail to resolve are skipped, as long as at least one other target works
+            let mut lookups = Vec::new();
+            let mut error = Error::NoRecords(domain.to_string());
+            for (srv, ttl) in srv_records {
+                info!("using SRV record:\n\t{srv}");
+
+                let chain = vec![question_name!(domain => SRV)];
+                match follow_target(connection, &srv.name, srv.port, ttl, resolver, chain).await {
+                    Ok(target_lookups) => lookups.extend(target_lookups),
+                    Err(e) => {
+                        warn!("failed to resolve SRV target `{}`: {e}", srv.name);
+                        error = e;
+                    }
+                }
+            }
+
+            return if lookups.is_empty() {
+                Err(error)
+            } else {
+                Ok(lookups)
+            };
+        }
+
+        resolve_address(connection, domain, port, resolver, Vec::new()).await
+    }
+
+    // checks A/AAAA and CNAME records for a domain, following CNAME records until addresses are found.
+    // boxed as async functions can't directly recurse
+    fn resolve_address<'a>(
+        connection: &'a Connection,
+        domain: &'a str,
+        port: u16,
+        resolver: &'a Resolver,
+        mut chain: Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Lookup>>> + Send + 'a>> {
+        Box::pin(async move {
+            // stop before following a misconfigured zone forever
+            let name = normalise_name(domain);
+            let looped = chain.iter().any(|step| normalise_name(step) == name);
+            chain.push(domain.to_string());
+
+            if looped {
+                return Err(Error::CnameLoop(chain));
+            } else if chain.len() > MAX_CHAIN_LENGTH {
+                return Err(Error::ChainTooLong(chain));
+            }
+
+            // check A/AAAA and CNAME records (in that order) and use results as discovered
+            let addresses = find_addresses(connection, domain, resolver.preference).await?;
+            if !addresses.is_empty() {
+                return Ok(addresses
+                    .into_iter()
+                    .map(|(ip, ttl, origin)| {
+                        info!("using address record:\n\t{ip}");
+
+                        let mut chain = chain.clone();
+                        chain.push(ip.to_string());
+                        Lookup {
+                            ip,
+                            port,
+                            ttl,
+                            chain,
+                            origin,
+                        }
+                    })
+                    .collect());
+            }
+
+            if let Some((cname, ttl)) = find_record!(connection, domain => CNAME)? {
+                info!("using CNAME record:\n\t{cname}");
+
+                follow_target(connection, &cname, port, ttl, resolver, chain).await
+            } else {
+                Err(Error::NoRecords(domain.to_string()))
+            }
+        })
+    }
+
+    // follows the target of a record, which is either an ip (and so the end of the search) or another domain
+    async fn follow_target(
+        connection: &Connection,
+        target: &str,
+        port: u16,
+        ttl: Duration,
+        resolver: &Resolver,
+        mut chain: Vec<String>,
+    ) -> Result<Vec<Lookup>> {
         // if record exists, check if we've reached an ip
-        if let Ok(ip) = IpAddr::from_str(&ip) {
+        let lookups = if let Ok(ip) = IpAddr::from_str(target) {
             // we've reached the end of the trail!
-            Ok((ip, port))
+            chain.push(ip.to_string());
+            vec![Lookup {
+                ip,
+                port,
+                ttl,
+                chain,
+                origin: Origin::Literal,
+            }]
+        } else if let Some(lookups) = lookup_hosts(target, port, resolver) {
+            lookups
+                .into_iter()
+                .map(|lookup| Lookup {
+                    chain: chain.iter().cloned().chain(lookup.chain).collect(),
+                    ..lookup
+                })
+                .collect()
         } else {
-            info!("continuing search for {ip}");
-            domain_lookup_inner(socket, &ip, port)
+            info!("continuing search for {target}");
+            resolve_address(connection, target, port, resolver, chain).await?
+        };
+
+        // each lookup is only valid for as long as every record along the way is
+        Ok(lookups
+            .into_iter()
+            .map(|lookup| Lookup {
+                ttl: lookup.ttl.min(ttl),
+                ..lookup
+            })
+            .collect())
+    }
+
+    // then try each name from the search list until one resolves
+    let mut error = None;
+    for name in config.candidate_names(domain) {
+        debug!("trying name {name}");
+
+        match domain_lookup_inner(&connection, &name, port, resolver).await {
+            Ok(mut lookups) => {
+                // the same address can be reached through more than one SRV target, but only needs to be tried once
+                let mut seen = HashSet::new();
+                lookups.retain(|lookup| seen.insert((lookup.ip, lookup.port)));
+                return Ok(lookups);
+            }
+            // the error for the name as given is the one that's reported, wherever it is in the list
+            Err(e) if name == domain => error = Some(e),
+            Err(e) => debug!("failed to look up {name}: {e}"),
         }
     }
 
-    domain_lookup_inner(&socket, domain, port)
+    Err(error.unwrap_or_else(|| Error::NoRecords(domain.to_string())))
 }
 
-/// looks up ip address for a given domain and port, checking SRV, CNAME and A records (in that order),
-/// while using the DNS servers specified
-fn domain_lookup_with_servers(
+/// looks up every address for a given domain and port, checking SRV, A/AAAA and CNAME records (in that order),
+/// while querying all of the DNS servers in the resolver's config at once and using the first successful result.
+///
+/// If every server fails, the error from each is returned
+pub(crate) async fn domain_lookup_with_servers(
     domain: &str,
     port: u16,
-    dns_servers: &[IpAddr],
-) -> Result<(IpAddr, u16)> {
-    dns_servers
-        .iter()
-        .filter_map(|dns_server| {
+    resolver: &Resolver,
+) -> Result<Vec<Lookup>, Vec<(Nameserver, Error)>> {
+    let mut lookups = JoinSet::new();
+    let resolver = Arc::new(resolver.clone());
+
+    for dns_server in resolver.config.nameservers.clone() {
+        let domain = domain.to_string();
+        let resolver = resolver.clone();
+
+        lookups.spawn(async move {
             info!("checking with DNS server {dns_server}");
-            domain_lookup_individual(domain, port, *dns_server).ok()
-        })
-        .next()
-        .ok_or(anyhow!("no valid records on any DNS servers"))
+            let lookup = domain_lookup_individual(&domain, port, &dns_server, &resolver).await;
+
+            (dns_server, lookup)
+        });
+    }
+
+    // any lookups still running are cancelled once the join set is dropped
+    let mut errors = Vec::new();
+    while let Some(lookup) = lookups.join_next().await {
+        match lookup {
+            Ok((_, Ok(lookups))) => return Ok(lookups),
+            Ok((dns_server, Err(e))) => {
+                debug!("DNS server {dns_server} failed to resolve {domain}: {e}");
+                errors.push((dns_server, e));
+            }
+            Err(e) => warn!("lookup task for {domain} failed: {e}"),
+        }
+    }
+
+    Err(errors)
 }
 
-/// looks up ip address for a given domain and port, checking SRV, CNAME and A records (in that order)
-pub fn domain_lookup(domain: &str, port: u16) -> Result<(IpAddr, u16)> {
-    lazy_static! {
-        static ref DEFAULT_DNS_SERVERS: Vec<IpAddr> =
-            vec!["1.1.1.1".parse().unwrap(), "1.0.0.1".parse().unwrap()];
+/// looks up ip address for a given domain and port using the default [Resolver], blocking until it's complete.
+/// See [Resolver::lookup_blocking]
+pub fn domain_lookup(domain: &str, port: u16) -> Result<Lookup> {
+    Resolver::default().lookup_blocking(domain, port)
+}
+
+/// looks up ip address for a given domain and port using the default [Resolver]. See [Resolver::lookup]
+pub async fn domain_lookup_async(domain: &str, port: u16) -> Result<Lookup> {
+    Resolver::default().lookup(domain, port).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FakeServer, Zone};
+    use std::{
+        collections::HashMap,
+        net::{Ipv4Addr, Ipv6Addr},
+    };
+
+    /// Finds the error from the only dns server a lookup was sent to
+    fn server_error(error: Error) -> Error {
+        match error {
+            Error::AllServersFailed { mut errors, .. } if errors.len() == 1 => errors.remove(0).1,
+            error => panic!("unexpected error {error:?}"),
+        }
     }
 
-    if let Ok(result) = domain_lookup_with_servers(domain, port, &crate::DNS_SERVERS) {
-        // first try with servers from OS
-        info!("successfully found ip address using OS dns servers");
+    #[tokio::test]
+    async fn test_srv_lookup() {
+        let server = FakeServer::start(
+            Zone::new()
+                .srv("_minecraft._tcp.srv.test", 0, 5, 25566, "mc.srv.test")
+                .a("mc.srv.test", Ipv4Addr::new(10, 0, 1, 1)),
+        );
 
-        Ok(result)
-    } else {
-        info!("trying default DNS servers `{:?}`", *DEFAULT_DNS_SERVERS);
-        // then just return result of using default servers
-        domain_lookup_with_servers(domain, port, &DEFAULT_DNS_SERVERS)
+        let lookup = server.resolver().lookup("srv.test", 25565).await.unwrap();
+        assert_eq!(lookup.ip, Ipv4Addr::new(10, 0, 1, 1));
+        assert_eq!(lookup.port, 25566);
+
+        // names from records are fully qualified, so end with a dot
+        assert_eq!(
+            lookup.chain,
+            ["_minecraft._tcp.srv.test", "mc.srv.test.", "10.0.1.1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_srv_failure() {
+        let server = FakeServer::start(
+            Zone::new()
+                .servfail("_minecraft._tcp.srvfail.test")
+                .a("srvfail.test", Ipv4Addr::new(10, 0, 1, 8)),
+        );
+
+        let lookup = server
+            .resolver()
+            .lookup("srvfail.test", 25565)
+            .await
+            .unwrap();
+        assert_eq!(lookup.ip, Ipv4Addr::new(10, 0, 1, 8));
+        assert_eq!(lookup.port, 25565);
+    }
+
+    #[tokio::test]
+    async fn test_address_family_failure() {
+        let server = FakeServer::start(
+            Zone::new()
+                .servfail_type("family.test", Type::A)
+                .aaaa("family.test", Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 7))
+                .servfail_type("both.family.test", Type::A)
+                .servfail_type("both.family.test", Type::AAAA),
+        );
+
+        // AAAA records are still used when the preferred A question fails
+        let lookup = server
+            .resolver()
+            .lookup("family.test", 25565)
+            .await
+            .unwrap();
+        assert_eq!(lookup.ip, Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 7));
+        assert_eq!(lookup.origin, Origin::Aaaa);
+
+        // and the error for the A question is kept if both fail
+        let error = server.resolver().lookup("both.family.test", 25565).await;
+        assert!(matches!(
+            server_error(error.unwrap_err()),
+            Error::ServFail { server: address, .. } if address == server.address()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_search_list() {
+        let server = FakeServer::start(Zone::new().a("mc.svc.local", Ipv4Addr::new(10, 0, 1, 10)));
+
+        let mut resolver = server.resolver();
+        resolver.config.search = vec!["svc.local".to_string()];
+
+        let lookup = resolver.lookup("mc", 25565).await.unwrap();
+        assert_eq!(lookup.ip, Ipv4Addr::new(10, 0, 1, 10));
+
+        // names are reported as given when they aren't found, whether they're tried before the search list or after
+        for name in ["missing.example.com", "missing"] {
+            let error = resolver.lookup(name, 25565).await;
+            assert!(matches!(
+                server_error(error.unwrap_err()),
+                Error::NxDomain(missing) if missing == name
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_static_hosts() {
+        let server = FakeServer::start(Zone::new().srv(
+            "_minecraft._tcp.static.test",
+            0,
+            5,
+            25566,
+            "mc-survival",
+        ));
+
+        let mut resolver = server.resolver();
+        resolver.static_hosts = HashMap::from([(
+            "MC-Survival.".to_string(),
+            "10.0.1.9:25567".parse().unwrap(),
+        )]);
+
+        // overrides are used for SRV targets, with their own port taking priority over the SRV record
+        let lookup = resolver.lookup("static.test", 25565).await.unwrap();
+        assert_eq!(
+            (lookup.ip, lookup.port),
+            (Ipv4Addr::new(10, 0, 1, 9).into(), 25567)
+        );
+        assert_eq!(lookup.origin, Origin::StaticOverride);
+
+        // and before asking any dns servers
+        let lookup = resolver.lookup("mc-survival", 25565).await.unwrap();
+        assert_eq!(lookup.ip, Ipv4Addr::new(10, 0, 1, 9));
+        assert_eq!(server.queries(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_srv_lookup_disabled() {
+        let server = FakeServer::start(
+            Zone::new()
+                .srv("_minecraft._tcp.nosrv.test", 0, 5, 25566, "java.nosrv.test")
+                .a("java.nosrv.test", Ipv4Addr::new(10, 0, 1, 6))
+                .a("nosrv.test", Ipv4Addr::new(10, 0, 1, 7)),
+        );
+
+        let mut resolver = server.resolver();
+        resolver.srv_lookup = false;
+
+        let lookup = resolver.lookup("nosrv.test", 19132).await.unwrap();
+        assert_eq!(lookup.ip, Ipv4Addr::new(10, 0, 1, 7));
+        assert_eq!(lookup.port, 19132);
+    }
+
+    #[tokio::test]
+    async fn test_cname_chain() {
+        let server = FakeServer::start(
+            Zone::new()
+                .ttl(30)
+                .cname("www.cname.test", "proxy.cname.test")
+                .ttl(300)
+                .cname("proxy.cname.test", "host.cname.test")
+                .ttl(60)
+                .a("host.cname.test", Ipv4Addr::new(10, 0, 1, 2)),
+        );
+
+        let lookup = server
+            .resolver()
+            .lookup("www.cname.test", 25565)
+            .await
+            .unwrap();
+        assert_eq!(lookup.ip, Ipv4Addr::new(10, 0, 1, 2));
+        assert_eq!(lookup.port, 25565);
+        assert_eq!(lookup.ttl, Duration::from_secs(30));
+        assert_eq!(
+            lookup.describe_chain(),
+            "www.cname.test -> proxy.cname.test. -> host.cname.test. -> 10.0.1.2"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cname_loop() {
+        let server = FakeServer::start(
+            Zone::new()
+                .cname("a.loop.test", "b.loop.test")
+                .cname("b.loop.test", "a.loop.test"),
+        );
+
+        let error = server.resolver().lookup("a.loop.test", 25565).await;
+        assert!(matches!(
+            server_error(error.unwrap_err()),
+            Error::CnameLoop(chain) if chain == ["a.loop.test", "b.loop.test.", "a.loop.test."]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_nxdomain_and_servfail() {
+        let server = FakeServer::start(Zone::new().servfail("broken.test"));
+
+        let error = server.resolver().lookup("missing.test", 25565).await;
+        assert!(matches!(
+            server_error(error.unwrap_err()),
+            Error::NxDomain(name) if name == "missing.test"
+        ));
+
+        let error = server.resolver().lookup("broken.test", 25565).await;
+        assert!(matches!(
+            server_error(error.unwrap_err()),
+            Error::ServFail { server: address, .. } if address == server.address()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_negative_caching() {
+        let server = FakeServer::start(
+            Zone::new()
+                .negative_ttl(60)
+                .a("exists.negative.test", Ipv4Addr::new(10, 0, 1, 4))
+                .servfail("broken.negative.test"),
+        );
+        let resolver = server.resolver();
+
+        // SRV and A questions both get NXDOMAIN, then are answered from the cache
+        for _ in 0..2 {
+            let error = resolver.lookup("missing.negative.test", 25565).await;
+            assert!(matches!(
+                server_error(error.unwrap_err()),
+                Error::NxDomain(_)
+            ));
+        }
+        assert_eq!(server.queries(), 2);
+
+        // SRV and AAAA questions have no answers, which are cached along with the A records
+        for _ in 0..2 {
+            let lookup = resolver.lookup("exists.negative.test", 25565).await;
+            assert_eq!(lookup.unwrap().ip, Ipv4Addr::new(10, 0, 1, 4));
+        }
+        assert_eq!(server.queries(), 5);
+
+        // SRV question gets NXDOMAIN, then SERVFAIL for the A and AAAA questions is cached for the server that sent it
+        for _ in 0..2 {
+            let error = resolver.lookup("broken.negative.test", 25565).await;
+            assert!(matches!(
+                server_error(error.unwrap_err()),
+                Error::ServFail { .. }
+            ));
+        }
+        assert_eq!(server.queries(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_negative_caching_per_server() {
+        let broken = FakeServer::start(Zone::new().negative_ttl(60));
+        let server = FakeServer::start(
+            Zone::new()
+                .negative_ttl(60)
+                .a("split.negative.test", Ipv4Addr::new(10, 0, 1, 5)),
+        );
+
+        let error = broken.resolver().lookup("split.negative.test", 25565).await;
+        assert!(matches!(
+            server_error(error.unwrap_err()),
+            Error::NxDomain(_)
+        ));
+
+        // the cached NXDOMAIN from one server doesn't stop the fallback servers being asked
+        let mut resolver = broken.resolver();
+        resolver.fallback_servers = vec![server.nameserver()];
+        let lookup = resolver.lookup("split.negative.test", 25565).await;
+        assert_eq!(lookup.unwrap().ip, Ipv4Addr::new(10, 0, 1, 5));
+        assert_eq!(broken.queries(), 2);
+
+        // or another configured server
+        let resolver = Resolver::with_nameservers(vec![broken.nameserver(), server.nameserver()]);
+        let lookup = resolver.lookup("split.negative.test", 25565).await;
+        assert_eq!(lookup.unwrap().ip, Ipv4Addr::new(10, 0, 1, 5));
+        assert_eq!(broken.queries(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let server = FakeServer::start(Zone::new().silent("slow.test"));
+
+        let mut resolver = server.resolver();
+        resolver.config.timeout = Duration::from_millis(100);
+        resolver.config.attempts = 2;
+
+        let error = resolver.lookup("slow.test", 25565).await;
+        assert!(matches!(
+            server_error(error.unwrap_err()),
+            Error::Timeout(address) if address == server.address()
+        ));
+
+        // SRV question is answered, then the A and AAAA questions are each sent once for each attempt
+        assert_eq!(server.queries(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_fallback() {
+        let server = FakeServer::start(
+            Zone::new()
+                .truncate_udp()
+                .a("big.test", Ipv4Addr::new(10, 0, 1, 3)),
+        );
+
+        let lookup = server.resolver().lookup("big.test", 25565).await.unwrap();
+        assert_eq!(lookup.ip, Ipv4Addr::new(10, 0, 1, 3));
+
+        // SRV over UDP, then A over UDP which is truncated and so asked again over TCP,
+        // then AAAA over UDP which has no answers to truncate
+        assert_eq!(server.queries(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_all() {
+        let server = FakeServer::start(
+            Zone::new()
+                .srv("_minecraft._tcp.rr.test", 0, 5, 25565, "a.rr.test")
+                .srv("_minecraft._tcp.rr.test", 5, 5, 25567, "missing.rr.test")
+                .srv("_minecraft._tcp.rr.test", 10, 5, 25566, "b.rr.test")
+                .srv("_minecraft._tcp.rr.test", 20, 5, 25565, "a.rr.test")
+                .a("a.rr.test", Ipv4Addr::new(10, 0, 3, 1))
+                .a("a.rr.test", Ipv4Addr::new(10, 0, 3, 2))
+                .aaaa("a.rr.test", "fd00::1".parse().unwrap())
+                .a("b.rr.test", Ipv4Addr::new(10, 0, 3, 3)),
+        );
+
+        let lookups = server
+            .resolver()
+            .lookup_all("rr.test", 25565)
+            .await
+            .unwrap();
+        let endpoints: Vec<_> = lookups
+            .iter()
+            .map(|lookup| (lookup.ip.to_string(), lookup.port, lookup.origin))
+            .collect();
+
+        // targets that don't resolve are skipped, and each address is only returned once
+        assert_eq!(
+            endpoints,
+            [
+                ("10.0.3.1".to_string(), 25565, Origin::A),
+                ("10.0.3.2".to_string(), 25565, Origin::A),
+                ("fd00::1".to_string(), 25565, Origin::Aaaa),
+                ("10.0.3.3".to_string(), 25566, Origin::A),
+            ]
+        );
     }
 }
@@ -0,0 +1,71 @@
+use crate::Nameserver;
+use rustdns::Rcode;
+use std::{fmt, net::SocketAddr};
+use thiserror::Error;
+
+/// Result type used throughout the dns crate
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Reasons a domain can fail to resolve
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A dns server didn't respond in time
+    #[error("dns server {0} timed out")]
+    Timeout(SocketAddr),
+    /// The domain doesn't exist
+    #[error("domain `{0}` does not exist (NXDOMAIN)")]
+    NxDomain(String),
+    /// A dns server was unable to answer a question about the domain
+    #[error("dns server {server} failed to answer for `{name}` (SERVFAIL)")]
+    ServFail { server: SocketAddr, name: String },
+    /// A dns server responded with an error other than NXDOMAIN or SERVFAIL
+    #[error("dns server {server} responded with {rcode} for `{name}`")]
+    Rcode {
+        server: SocketAddr,
+        name: String,
+        rcode: Rcode,
+    },
+    /// The domain exists, but has no records that lead to an ip address
+    #[error("no valid records for `{0}`")]
+    NoRecords(String),
+    /// A dns server sent a response that couldn't be understood
+    #[error("malformed response from dns server {server}: {reason}")]
+    MalformedResponse { server: SocketAddr, reason: String },
+    /// Following CNAME or SRV records led back to a domain that was already visited
+    #[error("CNAME loop found: {}", .0.join(" -> "))]
+    CnameLoop(Vec<String>),
+    /// Too many CNAME or SRV records were followed without reaching an ip address
+    #[error("gave up after following {} names: {}", .0.len(), .0.join(" -> "))]
+    ChainTooLong(Vec<String>),
+    /// A DNS-over-HTTPS server responded with a HTTP error
+    #[error("dns server {server} responded with HTTP status {status}")]
+    HttpStatus { server: SocketAddr, status: String },
+    /// A value couldn't be parsed
+    #[error("{0}")]
+    Parse(String),
+    /// There were no dns servers to ask
+    #[error("no dns servers configured")]
+    NoServers,
+    /// An error from the underlying network connection
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Every dns server failed to resolve the domain, along with the reason each one failed
+    #[error("no dns servers could resolve `{domain}`:{}", ServerErrors(.errors))]
+    AllServersFailed {
+        domain: String,
+        errors: Vec<(Nameserver, Error)>,
+    },
+}
+
+/// Helper to display the errors from each dns server on their own line
+struct ServerErrors<'a>(&'a [(Nameserver, Error)]);
+
+impl fmt::Display for ServerErrors<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (server, error) in self.0 {
+            write!(f, "\n\t{server}: {error}")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+use crate::{cache::normalise_name, Nameserver, Protocol, Resolver};
+use rustdns::Type;
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, UdpSocket},
+    sync::oneshot,
+};
+
+/// TTL given to records unless [Zone::ttl] is used
+const DEFAULT_TTL: u32 = 60;
+
+/// Record served by a [FakeServer]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FakeRecord {
+    /// Ipv4 address
+    A(Ipv4Addr),
+    /// Ipv6 address
+    Aaaa(Ipv6Addr),
+    /// Alias for another name
+    Cname(String),
+    /// Location of a service
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+}
+
+impl FakeRecord {
+    /// Numeric type of the record
+    fn record_type(&self) -> u16 {
+        match self {
+            FakeRecord::A(_) => 1,
+            FakeRecord::Aaaa(_) => 28,
+            FakeRecord::Cname(_) => 5,
+            FakeRecord::Srv { .. } => 33,
+        }
+    }
+
+    /// Encodes the data of the record, as it's sent after the TTL
+    fn data(&self) -> Vec<u8> {
+        match self {
+            FakeRecord::A(ip) => ip.octets().to_vec(),
+            FakeRecord::Aaaa(ip) => ip.octets().to_vec(),
+            FakeRecord::Cname(target) => encode_name(target),
+            FakeRecord::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => [priority, weight, port]
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .chain(encode_name(target))
+                .collect(),
+        }
+    }
+}
+
+/// How a [FakeServer] responds to questions about a single name
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    /// Answers with any matching records, along with their TTLs
+    Records(Vec<(FakeRecord, u32)>),
+    /// Responds with SERVFAIL
+    ServFail,
+    /// Never responds, so the question times out
+    Silent,
+}
+
+/// Names and records served by a [FakeServer]. Questions about names not in the zone get NXDOMAIN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    /// Every name in the zone, normalised
+    entries: HashMap<String, Entry>,
+    /// Normalised names and types of questions that fail with SERVFAIL, whatever the name's entry is
+    servfail_types: HashSet<(String, u16)>,
+    /// TTL given to records as they're added
+    ttl: u32,
+    /// Whether responses over UDP with any answers should be truncated, so they need to be asked again over TCP
+    truncate_udp: bool,
+    /// Negative TTL given in the SOA record sent with responses without any answers, if one should be sent
+    negative_ttl: Option<u32>,
+}
+
+impl Default for Zone {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            servfail_types: HashSet::new(),
+            ttl: DEFAULT_TTL,
+            truncate_udp: false,
+            negative_ttl: None,
+        }
+    }
+}
+
+impl Zone {
+    /// Creates an empty zone
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the TTL of any records added after this
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Truncates every response over UDP that has answers, so they need to be asked again over TCP
+    pub fn truncate_udp(mut self) -> Self {
+        self.truncate_udp = true;
+        self
+    }
+
+    /// Sends a SOA record with responses that have no answers (NXDOMAIN or no records of the type asked for),
+    /// allowing them to be cached for the given negative TTL
+    pub fn negative_ttl(mut self, ttl: u32) -> Self {
+        self.negative_ttl = Some(ttl);
+        self
+    }
+
+    /// Adds an A record
+    pub fn a(self, name: &str, ip: Ipv4Addr) -> Self {
+        self.record(name, FakeRecord::A(ip))
+    }
+
+    /// Adds an AAAA record
+    pub fn aaaa(self, name: &str, ip: Ipv6Addr) -> Self {
+        self.record(name, FakeRecord::Aaaa(ip))
+    }
+
+    /// Adds a CNAME record
+    pub fn cname(self, name: &str, target: &str) -> Self {
+        self.record(name, FakeRecord::Cname(target.to_string()))
+    }
+
+    /// Adds a SRV record
+    pub fn srv(self, name: &str, priority: u16, weight: u16, port: u16, target: &str) -> Self {
+        self.record(
+            name,
+            FakeRecord::Srv {
+                priority,
+                weight,
+                port,
+                target: target.to_string(),
+            },
+        )
+    }
+
+    /// Adds any record
+    pub fn record(mut self, name: &str, record: FakeRecord) -> Self {
+        let entry = self
+            .entries
+            .entry(normalise_name(name))
+            .or_insert_with(|| Entry::Records(Vec::new()));
+
+        if let Entry::Records(records) = entry {
+            records.push((record, self.ttl));
+        }
+
+        self
+    }
+
+    /// Makes questions about a name fail with SERVFAIL
+    pub fn servfail(mut self, name: &str) -> Self {
+        self.entries.insert(normalise_name(name), Entry::ServFail);
+        self
+    }
+
+    /// Makes questions about a name fail with SERVFAIL, but only for one type of record
+    pub fn servfail_type(mut self, name: &str, record_type: Type) -> Self {
+        self.servfail_types
+            .insert((normalise_name(name), record_type as u16));
+        self
+    }
+
+    /// Makes questions about a name go unanswered
+    pub fn silent(mut self, name: &str) -> Self {
+        self.entries.insert(normalise_name(name), Entry::Silent);
+        self
+    }
+
+    /// Builds the response to an encoded question, or `None` if it shouldn't be answered.
+    ///
+    /// Names with a CNAME record answer questions of any type with just the CNAME, like an
+    /// authoritative server for a CNAME pointing outside its zone
+    pub fn respond(&self, query: &[u8]) -> Option<Vec<u8>> {
+        self.build_response(query, false)
+    }
+
+    /// Builds a response to an encoded question, truncating it if needed
+    fn build_response(&self, query: &[u8], udp: bool) -> Option<Vec<u8>> {
+        // find the end of the name being asked about, then the type after it
+        let mut end = 12;
+        let mut labels = Vec::new();
+        loop {
+            let len = *query.get(end)? as usize;
+            if len == 0 {
+                break;
+            }
+
+            labels.push(String::from_utf8_lossy(query.get(end + 1..end + 1 + len)?).to_string());
+            end += len + 1;
+        }
+        let record_type = u16::from_be_bytes([*query.get(end + 1)?, *query.get(end + 2)?]);
+        let question = query.get(12..end + 5)?;
+
+        let name = normalise_name(&labels.join("."));
+        let (rcode, answers) = match self.entries.get(&name) {
+            _ if self.servfail_types.contains(&(name.clone(), record_type)) => (2, Vec::new()),
+            Some(Entry::Records(records)) => (
+                0,
+                records
+                    .iter()
+                    .filter(|(record, _)| {
+                        record.record_type() == record_type
+                            || matches!(record, FakeRecord::Cname(_))
+                    })
+                    .collect(),
+            ),
+            Some(Entry::ServFail) => (2, Vec::new()),
+            Some(Entry::Silent) => return None,
+            None => (3, Vec::new()),
+        };
+        let truncated = udp && self.truncate_udp && !answers.is_empty();
+        let soa = self
+            .negative_ttl
+            .filter(|_| rcode != 2 && answers.is_empty());
+
+        // header is the id, flags (response, authoritative, truncation and recursion desired), rcode,
+        // then the number of questions, answers, authority records and additional records
+        let mut response = query[0..2].to_vec();
+        response.push(0x84 | if truncated { 0x02 } else { 0 } | (query[2] & 0x01));
+        response.push(rcode);
+        response.extend_from_slice(&1u16.to_be_bytes());
+        let answer_count = if truncated { 0 } else { answers.len() as u16 };
+        response.extend_from_slice(&answer_count.to_be_bytes());
+        response.extend_from_slice(&u16::from(soa.is_some()).to_be_bytes());
+        response.extend_from_slice(&[0, 0]);
+        response.extend_from_slice(question);
+
+        for (record, ttl) in answers.iter().filter(|_| !truncated) {
+            let data = record.data();
+
+            // name is a pointer to the one in the question
+            response.extend_from_slice(&[0xc0, 12]);
+            response.extend_from_slice(&record.record_type().to_be_bytes());
+            response.extend_from_slice(&1u16.to_be_bytes());
+            response.extend_from_slice(&ttl.to_be_bytes());
+            response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            response.extend_from_slice(&data);
+        }
+
+        if let Some(ttl) = soa {
+            // primary name server and mailbox, then the serial, refresh, retry, expire and minimum (negative) TTL
+            let mut data = encode_name("ns.fake.test");
+            data.extend(encode_name("hostmaster.fake.test"));
+            for value in [1, 3600, 600, 86400, ttl] {
+                data.extend_from_slice(&u32::to_be_bytes(value));
+            }
+
+            // the zone is treated as starting at the name asked about
+            response.extend_from_slice(&[0xc0, 12]);
+            response.extend_from_slice(&6u16.to_be_bytes());
+            response.extend_from_slice(&1u16.to_be_bytes());
+            response.extend_from_slice(&ttl.to_be_bytes());
+            response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            response.extend_from_slice(&data);
+        }
+
+        Some(response)
+    }
+}
+
+/// Encodes a name as length prefixed labels
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+
+    encoded
+}
+
+/// Dns server serving a [Zone] over UDP and TCP on localhost, for use in tests.
+///
+/// The server runs on its own thread, so works with both blocking and async code, and stops when dropped
+pub struct FakeServer {
+    /// Address the server is listening on, for both UDP and TCP
+    address: SocketAddr,
+    /// Number of questions received
+    queries: Arc<AtomicUsize>,
+    /// Tells the server to stop
+    shutdown: Option<oneshot::Sender<()>>,
+    /// Thread the server is running on
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FakeServer {
+    /// Starts serving the given zone on a random port
+    pub fn start(zone: Zone) -> Self {
+        // UDP and TCP need the same port, which may already be taken for TCP
+        let (udp, tcp) = (0..10)
+            .find_map(|_| {
+                let udp = std::net::UdpSocket::bind("127.0.0.1:0").ok()?;
+                let tcp = std::net::TcpListener::bind(udp.local_addr().ok()?).ok()?;
+                Some((udp, tcp))
+            })
+            .expect("couldn't find a free port for fake dns server");
+        udp.set_nonblocking(true).unwrap();
+        tcp.set_nonblocking(true).unwrap();
+
+        let address = udp.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let (shutdown, shutdown_receiver) = oneshot::channel();
+
+        let zone = Arc::new(zone);
+        let queries_clone = queries.clone();
+        let thread = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            runtime.block_on(async move {
+                let udp = UdpSocket::from_std(udp).unwrap();
+                let tcp = TcpListener::from_std(tcp).unwrap();
+
+                tokio::spawn(serve_udp(udp, zone.clone(), queries_clone.clone()));
+                tokio::spawn(serve_tcp(tcp, zone, queries_clone));
+
+                // any tasks still running are cancelled once the runtime is dropped
+                let _ = shutdown_receiver.await;
+            });
+        });
+
+        Self {
+            address,
+            queries,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        }
+    }
+
+    /// Address the server is listening on, for both UDP and TCP
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The server as a [Nameserver] using UDP
+    pub fn nameserver(&self) -> Nameserver {
+        Nameserver::from(self.address)
+    }
+
+    /// The server as a [Nameserver] using TCP
+    pub fn tcp_nameserver(&self) -> Nameserver {
+        Nameserver {
+            address: self.address,
+            protocol: Protocol::Tcp,
+        }
+    }
+
+    /// Resolver which only uses this server
+    pub fn resolver(&self) -> Resolver {
+        Resolver::with_nameservers(vec![self.nameserver()])
+    }
+
+    /// Number of questions received so far, over both UDP and TCP
+    pub fn queries(&self) -> usize {
+        self.queries.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answers every question sent over UDP
+async fn serve_udp(socket: UdpSocket, zone: Arc<Zone>, queries: Arc<AtomicUsize>) {
+    let mut query = [0; 512];
+    while let Ok((len, client)) = socket.recv_from(&mut query).await {
+        queries.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(response) = zone.build_response(&query[..len], true) {
+            let _ = socket.send_to(&response, client).await;
+        }
+    }
+}
+
+/// Answers every question sent over TCP, with each connection handled separately
+async fn serve_tcp(listener: TcpListener, zone: Arc<Zone>, queries: Arc<AtomicUsize>) {
+    while let Ok((mut stream, _)) = listener.accept().await {
+        let zone = zone.clone();
+        let queries = queries.clone();
+
+        tokio::spawn(async move {
+            while let Ok(len) = stream.read_u16().await {
+                let mut query = vec![0; len as usize];
+                if stream.read_exact(&mut query).await.is_err() {
+                    break;
+                }
+                queries.fetch_add(1, Ordering::Relaxed);
+
+                if let Some(response) = zone.respond(&query) {
+                    let _ = stream.write_u16(response.len() as u16).await;
+                    let _ = stream.write_all(&response).await;
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,172 @@
+use crate::Error;
+use crate::{cache::normalise_name, ip_preference::AddressFamily, Lookup, Origin, Resolver};
+use log::{debug, info};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Location of the hosts file on this platform
+#[cfg(windows)]
+const HOSTS_PATH: &str = r"C:\Windows\System32\drivers\etc\hosts";
+/// Location of the hosts file on this platform
+#[cfg(not(windows))]
+const HOSTS_PATH: &str = "/etc/hosts";
+
+/// How long lookups answered from the hosts file or static overrides are valid for,
+/// as they have no TTL of their own
+const HOSTS_TTL: Duration = Duration::from_secs(300);
+
+/// Fixed address for a domain, used instead of looking it up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostOverride {
+    /// Ip address to use for the domain
+    pub ip: IpAddr,
+    /// Port to use for the domain, or the one originally requested if none is given
+    pub port: Option<u16>,
+}
+
+impl FromStr for HostOverride {
+    type Err = Error;
+
+    /// Parses either an ip (`10.0.0.5`, `2001:db8::1`) or an ip with port (`10.0.0.5:25565`, `[2001:db8::1]:25565`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(address) = SocketAddr::from_str(s) {
+            Ok(Self {
+                ip: address.ip(),
+                port: Some(address.port()),
+            })
+        } else if let Ok(ip) = IpAddr::from_str(s) {
+            Ok(Self { ip, port: None })
+        } else {
+            Err(Error::Parse(format!("invalid host override `{s}`")))
+        }
+    }
+}
+
+/// Looks up a domain using the resolver's static overrides and then the hosts file, without making any dns requests.
+/// Every address in the hosts file is returned, in the order given by the resolver's preference
+pub(crate) fn lookup_hosts(domain: &str, port: u16, resolver: &Resolver) -> Option<Vec<Lookup>> {
+    let name = normalise_name(domain);
+    let preference = resolver.preference;
+
+    // overrides are matched the same way as dns names, so case and a trailing dot don't matter
+    let host = resolver
+        .static_hosts
+        .iter()
+        .find_map(|(host_name, host)| (normalise_name(host_name) == name).then_some(host));
+    if let Some(host) = host {
+        info!("using static override for {domain}:\n\t{}", host.ip);
+
+        return Some(vec![Lookup {
+            ip: host.ip,
+            port: host.port.unwrap_or(port),
+            ttl: HOSTS_TTL,
+            chain: vec![domain.to_string(), host.ip.to_string()],
+            origin: Origin::StaticOverride,
+        }]);
+    }
+
+    // hosts file is read every time so changes are picked up without restarting
+    let hosts = std::fs::read_to_string(HOSTS_PATH).ok()?;
+    let ips = parse_hosts(&hosts).remove(&name)?;
+
+    let lookups: Vec<_> = preference
+        .order()
+        .iter()
+        .flat_map(|family| {
+            ips.iter().filter(move |ip| match family {
+                AddressFamily::Ipv4 => ip.is_ipv4(),
+                AddressFamily::Ipv6 => ip.is_ipv6(),
+            })
+        })
+        .map(|&ip| Lookup {
+            ip,
+            port,
+            ttl: HOSTS_TTL,
+            chain: vec![domain.to_string(), ip.to_string()],
+            origin: Origin::HostsFile,
+        })
+        .collect();
+
+    if lookups.is_empty() {
+        debug!("hosts file entries for {domain} don't match preference {preference:?}");
+        None
+    } else {
+        info!(
+            "using hosts file entries for {domain}:\n\t{}",
+            lookups
+                .iter()
+                .map(|lookup| lookup.ip.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Some(lookups)
+    }
+}
+
+/// Parses the contents of a hosts file into a map of names to every ip listed for them
+fn parse_hosts(hosts: &str) -> HashMap<String, Vec<IpAddr>> {
+    let mut entries: HashMap<String, Vec<IpAddr>> = HashMap::new();
+
+    for line in hosts.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+
+        // each line is an ip followed by any number of names, with an optional zone on ipv6 addresses
+        let Some(ip) = words
+            .next()
+            .and_then(|ip| IpAddr::from_str(ip.split('%').next()?).ok())
+        else {
+            continue;
+        };
+
+        for name in words {
+            entries.entry(normalise_name(name)).or_default().push(ip);
+        }
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hosts() {
+        let hosts = parse_hosts(
+            "127.0.0.1 localhost
+            ::1 localhost ip6-localhost # loopback
+            172.18.0.5\tmc-survival MC-Creative.
+            not-an-ip broken",
+        );
+
+        assert_eq!(
+            hosts["localhost"],
+            [
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            hosts["mc-creative"],
+            ["172.18.0.5".parse::<IpAddr>().unwrap()]
+        );
+        assert!(!hosts.contains_key("broken"));
+    }
+
+    #[test]
+    fn test_host_override() {
+        let host: HostOverride = "[2001:db8::1]:25566".parse().unwrap();
+        assert_eq!(host.ip, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(host.port, Some(25566));
+
+        let host: HostOverride = "10.0.0.5".parse().unwrap();
+        assert_eq!(host.port, None);
+
+        assert!("mc-survival".parse::<HostOverride>().is_err());
+    }
+}
@@ -0,0 +1,49 @@
+use crate::Error;
+use std::str::FromStr;
+
+/// Which kind of address record (A or AAAA) should be used when a domain has both
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpPreference {
+    /// Try A records first, then fall back to AAAA records
+    #[default]
+    Ipv4First,
+    /// Try AAAA records first, then fall back to A records
+    Ipv6First,
+    /// Only ever use A records
+    Ipv4Only,
+    /// Only ever use AAAA records
+    Ipv6Only,
+}
+
+/// A single address family, used to describe the order records should be queried in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl IpPreference {
+    /// Address families to query, in the order they should be tried
+    pub(crate) fn order(self) -> &'static [AddressFamily] {
+        match self {
+            IpPreference::Ipv4First => &[AddressFamily::Ipv4, AddressFamily::Ipv6],
+            IpPreference::Ipv6First => &[AddressFamily::Ipv6, AddressFamily::Ipv4],
+            IpPreference::Ipv4Only => &[AddressFamily::Ipv4],
+            IpPreference::Ipv6Only => &[AddressFamily::Ipv6],
+        }
+    }
+}
+
+impl FromStr for IpPreference {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ipv4" | "ipv4_first" => Ok(IpPreference::Ipv4First),
+            "ipv6" | "ipv6_first" => Ok(IpPreference::Ipv6First),
+            "ipv4_only" => Ok(IpPreference::Ipv4Only),
+            "ipv6_only" => Ok(IpPreference::Ipv6Only),
+            _ => Err(Error::Parse(format!("unknown ip preference `{s}`"))),
+        }
+    }
+}
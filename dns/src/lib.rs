@@ -1,7 +1,25 @@
 #![deny(unsafe_code)]
 
+mod cache;
+mod connection;
 mod domain_lookup;
+mod error;
+#[cfg(any(test, feature = "test-server"))]
+mod fake_server;
+mod hosts;
+mod ip_preference;
+mod resolver;
+mod resolver_config;
 mod servers;
+mod srv;
+mod transport;
 
-pub use domain_lookup::domain_lookup;
-use servers::DNS_SERVERS;
+pub use domain_lookup::{domain_lookup, domain_lookup_async, Lookup, Origin};
+pub use error::{Error, Result};
+#[cfg(any(test, feature = "test-server"))]
+pub use fake_server::{FakeRecord, FakeServer, Zone};
+pub use hosts::HostOverride;
+pub use ip_preference::IpPreference;
+pub use resolver::{Resolver, DEFAULT_FALLBACK_SERVERS};
+pub use resolver_config::{ConfigSource, ResolverConfig};
+pub use transport::{add_tls_root_certificate, Nameserver, Protocol};
@@ -0,0 +1,130 @@
+use crate::{
+    domain_lookup::domain_lookup_with_servers, hosts::lookup_hosts, servers::SYSTEM_CONFIG, Error,
+    HostOverride, IpPreference, Lookup, Nameserver, ResolverConfig, Result,
+};
+use log::info;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+/// Dns servers used as a fallback by [Resolver::default] ([Cloudflare](https://1.1.1.1/dns/))
+pub const DEFAULT_FALLBACK_SERVERS: [SocketAddr; 2] = [
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 53),
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 0, 0, 1)), 53),
+];
+
+/// Looks up domains using a set of dns servers, falling back to another set if none of them succeed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolver {
+    /// Configuration used for lookups, including the dns servers to ask first
+    pub config: ResolverConfig,
+    /// Dns servers to ask if none of the servers in `config` could resolve a domain.
+    /// Fallback is disabled if this is empty
+    pub fallback_servers: Vec<Nameserver>,
+    /// Whether to prefer ipv4 or ipv6 addresses when a domain has both
+    pub preference: IpPreference,
+    /// Whether to check for `_minecraft._tcp` SRV records before address records.
+    /// Bedrock clients never use SRV records, so this should be disabled when looking up bedrock servers
+    pub srv_lookup: bool,
+    /// Fixed addresses for domains, which are used before the hosts file or any dns servers
+    pub static_hosts: HashMap<String, HostOverride>,
+}
+
+impl Default for Resolver {
+    /// Uses the dns servers and options from the operating system, falling back to [DEFAULT_FALLBACK_SERVERS]
+    fn default() -> Self {
+        Self {
+            config: SYSTEM_CONFIG.clone(),
+            fallback_servers: DEFAULT_FALLBACK_SERVERS.map(Nameserver::from).to_vec(),
+            preference: IpPreference::default(),
+            srv_lookup: true,
+            static_hosts: HashMap::new(),
+        }
+    }
+}
+
+impl Resolver {
+    /// Creates a resolver which only uses the given dns servers, without any fallback or options from the OS
+    pub fn with_nameservers(nameservers: Vec<Nameserver>) -> Self {
+        Self {
+            config: ResolverConfig {
+                nameservers,
+                ..Default::default()
+            },
+            fallback_servers: Vec::new(),
+            preference: IpPreference::default(),
+            srv_lookup: true,
+            static_hosts: HashMap::new(),
+        }
+    }
+
+    /// looks up ip address for a given domain and port, checking SRV, A/AAAA and CNAME records (in that order)
+    pub async fn lookup(&self, domain: &str, port: u16) -> Result<Lookup> {
+        // lookups always have at least one address if they succeed
+        Ok(self.lookup_all(domain, port).await?.remove(0))
+    }
+
+    /// Blocking version of [Resolver::lookup], see [Resolver::lookup_all_blocking]
+    pub fn lookup_blocking(&self, domain: &str, port: u16) -> Result<Lookup> {
+        Ok(self.lookup_all_blocking(domain, port)?.remove(0))
+    }
+
+    /// looks up every address for a given domain and port, checking SRV (unless `srv_lookup` is disabled), A/AAAA
+    /// and CNAME records (in that order).
+    ///
+    /// Addresses are in the order they should be tried: by the priority and weight of any SRV records,
+    /// then with A and AAAA records in the order given by `preference`. The list is never empty
+    pub async fn lookup_all(&self, domain: &str, port: u16) -> Result<Vec<Lookup>> {
+        // static overrides and the hosts file take priority over any dns servers
+        if let Some(result) = lookup_hosts(domain, port, self) {
+            return Ok(result);
+        }
+
+        // first try with configured servers
+        let mut errors = match domain_lookup_with_servers(domain, port, self).await {
+            Ok(result) => {
+                info!("successfully found ip address using configured dns servers");
+                return Ok(result);
+            }
+            Err(errors) => errors,
+        };
+
+        if !self.fallback_servers.is_empty() {
+            info!("trying fallback DNS servers `{:?}`", self.fallback_servers);
+            // then try fallback servers, keeping the rest of the config
+            let fallback = Resolver {
+                config: ResolverConfig {
+                    nameservers: self.fallback_servers.clone(),
+                    ..self.config.clone()
+                },
+                ..self.clone()
+            };
+
+            match domain_lookup_with_servers(domain, port, &fallback).await {
+                Ok(result) => return Ok(result),
+                Err(fallback_errors) => errors.extend(fallback_errors),
+            }
+        }
+
+        if errors.is_empty() {
+            Err(Error::NoServers)
+        } else {
+            Err(Error::AllServersFailed {
+                domain: domain.to_string(),
+                errors,
+            })
+        }
+    }
+
+    /// looks up every address for a given domain and port, checking SRV, A/AAAA and CNAME records (in that order)
+    ///
+    /// This blocks the current thread until the lookup is complete, so must not be called from async code -
+    /// use [Resolver::lookup_all] there instead
+    pub fn lookup_all_blocking(&self, domain: &str, port: u16) -> Result<Vec<Lookup>> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(self.lookup_all(domain, port))
+    }
+}
@@ -0,0 +1,121 @@
+use crate::Nameserver;
+use std::{fmt, path::PathBuf, time::Duration};
+
+/// Where the dns servers in a [ResolverConfig] came from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// Set directly, rather than read from the operating system
+    #[default]
+    Manual,
+    /// A resolv.conf file, such as `/etc/resolv.conf` or the one systemd-resolved writes its upstream servers to
+    ResolvConf(PathBuf),
+    /// The dns servers systemd-resolved uses for each network link
+    SystemdResolvedLinks,
+    /// The dns servers of each network adapter on windows
+    WindowsAdapters,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Manual => write!(f, "manual configuration"),
+            ConfigSource::ResolvConf(path) => write!(f, "{}", path.display()),
+            ConfigSource::SystemdResolvedLinks => write!(f, "systemd-resolved link configuration"),
+            ConfigSource::WindowsAdapters => write!(f, "GetAdaptersAddresses"),
+        }
+    }
+}
+
+/// Configuration for how lookups are performed, usually read from the operating system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolverConfig {
+    /// Dns servers to send questions to
+    pub nameservers: Vec<Nameserver>,
+    /// Domains to try appending to names that are looked up
+    pub search: Vec<String>,
+    /// Number of dots a name needs to be tried as-is before the search list is used
+    pub ndots: usize,
+    /// How long to wait for a dns server to respond to a single question
+    pub timeout: Duration,
+    /// How many times a question is sent to a dns server before giving up on it
+    pub attempts: usize,
+    /// Where the dns servers came from
+    pub source: ConfigSource,
+}
+
+impl Default for ResolverConfig {
+    /// Matches the defaults used by glibc when resolv.conf doesn't specify any options
+    fn default() -> Self {
+        Self {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+            source: ConfigSource::Manual,
+        }
+    }
+}
+
+impl ResolverConfig {
+    /// Names to try looking up for the given domain, in order, after applying the search list
+    pub(crate) fn candidate_names(&self, domain: &str) -> Vec<String> {
+        // names ending in a dot are already fully qualified, so never use the search list
+        if domain.ends_with('.') || self.search.is_empty() {
+            return vec![domain.to_string()];
+        }
+
+        let searched = self
+            .search
+            .iter()
+            .map(|search| format!("{domain}.{}", search.trim_end_matches('.')));
+
+        if domain.matches('.').count() >= self.ndots {
+            std::iter::once(domain.to_string())
+                .chain(searched)
+                .collect()
+        } else {
+            searched
+                .chain(std::iter::once(domain.to_string()))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidate_names() {
+        let config = ResolverConfig {
+            search: vec![
+                "svc.cluster.local".to_string(),
+                "cluster.local.".to_string(),
+            ],
+            ndots: 2,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.candidate_names("mc-survival"),
+            [
+                "mc-survival.svc.cluster.local",
+                "mc-survival.cluster.local",
+                "mc-survival"
+            ]
+        );
+        assert_eq!(
+            config.candidate_names("play.example.com"),
+            [
+                "play.example.com",
+                "play.example.com.svc.cluster.local",
+                "play.example.com.cluster.local"
+            ]
+        );
+        assert_eq!(
+            config.candidate_names("play.example.com."),
+            ["play.example.com."]
+        );
+    }
+}
@@ -1,31 +1,31 @@
+use crate::ResolverConfig;
 use cfg_if::cfg_if;
 use lazy_static::lazy_static;
 use log::debug;
-use std::net::IpAddr;
 
 cfg_if! {
     if #[cfg(unix)] {
         mod unix;
-        use unix::find_servers;
+        use unix::find_config;
     } else if #[cfg(windows)] {
         #[allow(unsafe_code)]
         mod windows;
 
-        use self::windows::find_servers;
+        use self::windows::find_config;
     } else {
-        fn find_servers() -> Option<Vec<IpAddr>> {
-            info!("no supported method for getting dns servers on this platform");
+        fn find_config() -> Option<ResolverConfig> {
+            log::info!("no supported method for getting dns servers on this platform");
             None
         }
     }
 }
 
 lazy_static! {
-    pub static ref DNS_SERVERS: Vec<IpAddr> = {
-        let servers = find_servers().unwrap_or_default();
-        debug!("using dns servers:\n{servers:#?}");
+    pub static ref SYSTEM_CONFIG: ResolverConfig = {
+        let config = find_config().unwrap_or_default();
+        debug!("using resolver config:\n{config:#?}");
 
-        servers
+        config
     };
 }
 
@@ -34,10 +34,11 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_find_servers() {
-        let servers = find_servers();
+    #[ignore = "depends on the dns configuration of the machine running the tests"]
+    fn test_find_config() {
+        let config = find_config();
 
-        dbg!(&servers);
-        assert!(servers.is_some());
+        dbg!(&config);
+        assert!(config.is_some_and(|config| !config.nameservers.is_empty()));
     }
 }
@@ -1,22 +1,297 @@
-use log::info;
-use std::{net::IpAddr, str::FromStr};
-
-/// Finds dns server in use by parsing /etc/resolv.conf
-pub(crate) fn find_servers() -> Option<Vec<IpAddr>> {
-    let resolv_conf = std::fs::read_to_string("/etc/resolv.conf").ok()?;
-
-    // parse resolv.conf, taking any lines that start with "nameserver " and attempting to parse the ip
-    let servers: Vec<_> = resolv_conf
-        .lines()
-        .filter_map(|line| line.strip_prefix("nameserver "))
-        .filter_map(|line| IpAddr::from_str(line).ok())
+use crate::{ConfigSource, Nameserver, ResolverConfig};
+use log::{debug, info, warn};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+/// Addresses of the stub resolvers run by systemd-resolved, which forward questions to the real dns servers
+const SYSTEMD_STUBS: [Ipv4Addr; 2] = [Ipv4Addr::new(127, 0, 0, 53), Ipv4Addr::new(127, 0, 0, 54)];
+
+/// Files listing the dns servers that systemd-resolved (or NetworkManager) forwards questions to,
+/// relative to the root directory and checked in order
+const UPSTREAM_RESOLV_CONFS: [&str; 2] = [
+    "run/systemd/resolve/resolv.conf",
+    "run/NetworkManager/no-stub-resolv.conf",
+];
+
+/// Directory where systemd-resolved keeps the dns servers and domains for each network link, relative to the root directory
+const SYSTEMD_LINKS: &str = "run/systemd/resolve/netif";
+
+/// Finds resolver configuration in use by parsing /etc/resolv.conf, looking past the systemd-resolved stub if it's in use
+pub(crate) fn find_config() -> Option<ResolverConfig> {
+    find_config_in(Path::new("/"))
+}
+
+/// Finds resolver configuration using the files under the given root directory
+fn find_config_in(root: &Path) -> Option<ResolverConfig> {
+    let path = root.join("etc/resolv.conf");
+    let mut config = parse_resolv_conf(&std::fs::read_to_string(&path).ok()?);
+    config.source = ConfigSource::ResolvConf(path);
+
+    // the stub doesn't always forward every type of question, so ask the servers behind it directly
+    if uses_systemd_stub(&config) {
+        info!("/etc/resolv.conf uses the systemd-resolved stub, looking for upstream nameservers");
+
+        match find_upstream_config(root, &config) {
+            Some(upstream) => config = upstream,
+            None => warn!("no upstream nameservers found, using the systemd-resolved stub"),
+        }
+    }
+
+    if !config.nameservers.is_empty() {
+        info!("using nameservers from {}", config.source);
+    } else {
+        info!("no valid nameservers in {}", config.source);
+    }
+
+    Some(config)
+}
+
+/// Whether every nameserver is a systemd-resolved stub
+fn uses_systemd_stub(config: &ResolverConfig) -> bool {
+    !config.nameservers.is_empty()
+        && config.nameservers.iter().all(|nameserver| {
+            matches!(nameserver.address.ip(), IpAddr::V4(ip) if SYSTEMD_STUBS.contains(&ip))
+        })
+}
+
+/// Finds the nameservers the systemd-resolved stub forwards to, first from the resolv.conf files it (or NetworkManager)
+/// writes, then from the configuration of each network link. Options not given by links are kept from `stub_config`
+fn find_upstream_config(root: &Path, stub_config: &ResolverConfig) -> Option<ResolverConfig> {
+    for file in UPSTREAM_RESOLV_CONFS {
+        let path = root.join(file);
+        let Ok(resolv_conf) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+
+        let config = parse_resolv_conf(&resolv_conf);
+        if !config.nameservers.is_empty() && !uses_systemd_stub(&config) {
+            return Some(ResolverConfig {
+                source: ConfigSource::ResolvConf(path),
+                ..config
+            });
+        }
+    }
+
+    // links are named after their index, so sort them to keep the order stable
+    let mut links: Vec<_> = std::fs::read_dir(root.join(SYSTEMD_LINKS))
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
         .collect();
+    links.sort();
+
+    let mut config = ResolverConfig {
+        nameservers: Vec::new(),
+        search: Vec::new(),
+        source: ConfigSource::SystemdResolvedLinks,
+        ..stub_config.clone()
+    };
+    for link in links {
+        let Ok(link) = std::fs::read_to_string(link) else {
+            continue;
+        };
+
+        for line in link.lines() {
+            match line.split_once('=') {
+                Some(("SERVERS", servers)) => {
+                    for nameserver in servers.split_whitespace().filter_map(parse_link_server) {
+                        if !config.nameservers.contains(&nameserver) {
+                            config.nameservers.push(nameserver);
+                        }
+                    }
+                }
+                // domains starting with ~ are only used to pick which link to ask, not for searching
+                Some(("DOMAINS", domains)) => config.search.extend(
+                    domains
+                        .split_whitespace()
+                        .filter(|domain| !domain.starts_with('~'))
+                        .map(str::to_string),
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    (!config.nameservers.is_empty()).then_some(config)
+}
+
+/// Parses a single nameserver from a systemd-resolved link, which may have a port and a server name
+/// (such as `[fe80::1%2]:53#dns.example.com`)
+fn parse_link_server(server: &str) -> Option<Nameserver> {
+    let server = server.split('#').next()?;
+
+    SocketAddr::from_str(server)
+        .ok()
+        .or_else(|| parse_nameserver(server))
+        .map(Nameserver::from)
+}
+
+/// Parses the contents of a resolv.conf file, as described in `man 5 resolv.conf`
+pub(crate) fn parse_resolv_conf(resolv_conf: &str) -> ResolverConfig {
+    let mut config = ResolverConfig::default();
 
-    if servers.len() > 0 {
-        info!("using nameservers from /etc/resolv.conf");
-        Some(servers)
+    for line in resolv_conf.lines() {
+        // ignore comments, which can start with either # or ;
+        let line = line.split(['#', ';']).next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+
+        match words.next() {
+            Some("nameserver") => match words.next().and_then(parse_nameserver) {
+                Some(nameserver) => config.nameservers.push(nameserver.into()),
+                None => warn!("invalid nameserver line in resolv.conf `{line}`"),
+            },
+            // domain and search are mutually exclusive, so whichever comes last is used
+            Some("domain") => config.search = words.take(1).map(str::to_string).collect(),
+            Some("search") => config.search = words.map(str::to_string).collect(),
+            Some("options") => words.for_each(|option| parse_option(&mut config, option)),
+            _ => {}
+        }
+    }
+
+    config
+}
+
+/// Parses a single nameserver address, which may be an ipv6 address with a scope (such as `fe80::1%eth0`)
+fn parse_nameserver(nameserver: &str) -> Option<SocketAddr> {
+    if let Some((ip, scope)) = nameserver.split_once('%') {
+        let ip = Ipv6Addr::from_str(ip).ok()?;
+
+        // scope can either be an interface index or an interface name
+        let scope_id = match scope.parse() {
+            Ok(scope_id) => scope_id,
+            Err(_) => interface_index(scope)?,
+        };
+
+        Some(SocketAddr::V6(SocketAddrV6::new(ip, 53, 0, scope_id)))
     } else {
-        info!("no valid nameservers in /etc/resolv.conf");
-        None
+        let ip = IpAddr::from_str(nameserver).ok()?;
+
+        Some(SocketAddr::new(ip, 53))
+    }
+}
+
+/// Finds the index of a network interface from its name
+fn interface_index(interface: &str) -> Option<u32> {
+    let index = std::fs::read_to_string(format!("/sys/class/net/{interface}/ifindex")).ok()?;
+
+    index.trim().parse().ok()
+}
+
+/// Applies a single option from an `options` line, ignoring any that aren't supported.
+/// Values are capped at the same limits glibc uses
+fn parse_option(config: &mut ResolverConfig, option: &str) {
+    let (name, value) = match option.split_once(':') {
+        Some((name, value)) => (name, value.parse::<usize>().ok()),
+        None => (option, None),
+    };
+
+    match (name, value) {
+        ("ndots", Some(ndots)) => config.ndots = ndots.min(15),
+        ("timeout", Some(timeout)) => {
+            config.timeout = Duration::from_secs(timeout.clamp(1, 30) as u64)
+        }
+        ("attempts", Some(attempts)) => config.attempts = attempts.clamp(1, 5),
+        // every dns server is asked at once, so there's no order to rotate
+        ("rotate", None) => debug!("ignoring resolv.conf option `rotate`"),
+        _ => info!("ignoring unsupported resolv.conf option `{option}`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resolv_conf() {
+        let config = parse_resolv_conf(
+            "# generated by docker
+            nameserver 10.96.0.10
+            nameserver fe80::1%2 ; link local
+            nameserver not-an-ip
+            domain example.com
+            search default.svc.cluster.local svc.cluster.local
+            options ndots:5 timeout:2 attempts:9 rotate edns0",
+        );
+
+        assert_eq!(
+            config,
+            ResolverConfig {
+                nameservers: vec![
+                    "10.96.0.10:53".parse().unwrap(),
+                    "[fe80::1%2]:53".parse().unwrap(),
+                ],
+                search: vec![
+                    "default.svc.cluster.local".to_string(),
+                    "svc.cluster.local".to_string(),
+                ],
+                ndots: 5,
+                timeout: Duration::from_secs(2),
+                attempts: 5,
+                source: ConfigSource::Manual,
+            }
+        );
+    }
+
+    #[test]
+    fn test_systemd_stub() {
+        let root = std::env::temp_dir().join(format!("dns-test-systemd-{}", std::process::id()));
+        let write = |file: &str, contents: &str| {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        let nameservers = |config: ResolverConfig| {
+            config
+                .nameservers
+                .into_iter()
+                .map(|nameserver| nameserver.address.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        write(
+            "etc/resolv.conf",
+            "nameserver 127.0.0.53\noptions edns0 trust-ad ndots:2\nsearch lan",
+        );
+
+        // no upstream servers, so the stub is still used
+        let config = find_config_in(&root).unwrap();
+        assert_eq!(nameservers(config), ["127.0.0.53:53"]);
+
+        // servers from each link are used if there's no upstream resolv.conf
+        write(
+            "run/systemd/resolve/netif/2",
+            "# This is private data. Do not parse.\nSERVERS=192.168.1.1 fe80::1%2\nDOMAINS=lan ~corp.example.com",
+        );
+        write(
+            "run/systemd/resolve/netif/3",
+            "SERVERS=10.0.0.53:5353#dns.corp.example.com 192.168.1.1",
+        );
+
+        let config = find_config_in(&root).unwrap();
+        assert_eq!(config.source, ConfigSource::SystemdResolvedLinks);
+        assert_eq!(config.search, ["lan"]);
+        assert_eq!(config.ndots, 2);
+        assert_eq!(
+            nameservers(config),
+            ["192.168.1.1:53", "[fe80::1%2]:53", "10.0.0.53:5353"]
+        );
+
+        // upstream resolv.conf is preferred over links
+        write(
+            "run/systemd/resolve/resolv.conf",
+            "nameserver 192.168.1.1\nsearch lan",
+        );
+
+        let config = find_config_in(&root).unwrap();
+        assert_eq!(
+            config.source,
+            ConfigSource::ResolvConf(root.join("run/systemd/resolve/resolv.conf"))
+        );
+        assert_eq!(nameservers(config), ["192.168.1.1:53"]);
+
+        std::fs::remove_dir_all(&root).unwrap();
     }
 }
@@ -1,15 +1,23 @@
 mod adapter_info;
 
+use crate::{ConfigSource, ResolverConfig};
 use adapter_info::AdapterInfoList;
 use log::info;
-use std::net::IpAddr;
+use std::net::SocketAddr;
 
-pub fn find_servers() -> Option<Vec<IpAddr>> {
+pub fn find_config() -> Option<ResolverConfig> {
     let servers = AdapterInfoList::new()?.dns_servers();
 
-    if servers.len() > 0 {
+    if !servers.is_empty() {
         info!("using nameservers from GetAdaptersAddresses");
-        Some(servers)
+        Some(ResolverConfig {
+            nameservers: servers
+                .into_iter()
+                .map(|server| SocketAddr::new(server, 53).into())
+                .collect(),
+            source: ConfigSource::WindowsAdapters,
+            ..Default::default()
+        })
     } else {
         info!("no valid nameservers from GetAdaptersAddresses");
         None
@@ -0,0 +1,120 @@
+use rand::Rng;
+use rustdns::SRV;
+use std::time::Duration;
+
+/// Orders SRV records in the order their targets should be tried, as described in
+/// [RFC 2782](https://datatracker.ietf.org/doc/html/rfc2782).
+///
+/// Records with a lower priority always come first, and records sharing a priority are shuffled
+/// randomly with the chance of being picked next proportional to their weight.
+/// A lone record with a target of `.` means the service is unavailable, so produces no targets
+pub(crate) fn order_srv_records(
+    records: Vec<(SRV, Duration)>,
+    rng: &mut impl Rng,
+) -> Vec<(SRV, Duration)> {
+    if let [(srv, _)] = records.as_slice() {
+        if srv.name.trim_end_matches('.').is_empty() {
+            return Vec::new();
+        }
+    }
+
+    let mut records = records;
+    records.sort_by_key(|(srv, _)| srv.priority);
+
+    let mut ordered = Vec::with_capacity(records.len());
+    let mut remaining = records.as_slice();
+
+    while let Some((first, _)) = remaining.first() {
+        // split off every record with the current lowest priority
+        let len = remaining
+            .iter()
+            .take_while(|(srv, _)| srv.priority == first.priority)
+            .count();
+        let (group, rest) = remaining.split_at(len);
+        remaining = rest;
+
+        // records with weight 0 go first so they have a small chance of being selected
+        let mut group = group.to_vec();
+        group.sort_by_key(|(srv, _)| srv.weight != 0);
+
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|(srv, _)| srv.weight as u32).sum();
+            let chosen = rng.gen_range(0..=total);
+
+            // pick first record whose running sum of weights is at least the chosen value
+            let mut running_sum = 0;
+            let index = group
+                .iter()
+                .position(|(srv, _)| {
+                    running_sum += srv.weight as u32;
+                    running_sum >= chosen
+                })
+                .unwrap_or(group.len() - 1);
+
+            ordered.push(group.remove(index));
+        }
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn srv(name: &str, priority: u16, weight: u16) -> (SRV, Duration) {
+        let srv = SRV {
+            priority,
+            weight,
+            port: 25565,
+            name: name.to_string(),
+        };
+
+        (srv, Duration::from_secs(60))
+    }
+
+    fn names(records: &[(SRV, Duration)]) -> Vec<&str> {
+        records.iter().map(|(srv, _)| srv.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let records = vec![srv("c", 30, 5), srv("a", 10, 5), srv("b", 20, 5)];
+
+        let ordered = order_srv_records(records, &mut rng);
+        assert_eq!(names(&ordered), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_weighted_order() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut heavy_first = 0;
+
+        for _ in 0..1000 {
+            let records = vec![
+                srv("light", 10, 1),
+                srv("heavy", 10, 99),
+                srv("backup", 20, 0),
+            ];
+            let ordered = order_srv_records(records, &mut rng);
+
+            assert_eq!(ordered.len(), 3);
+            assert_eq!(names(&ordered)[2], "backup");
+
+            if names(&ordered)[0] == "heavy" {
+                heavy_first += 1;
+            }
+        }
+
+        assert!(heavy_first > 900);
+    }
+
+    #[test]
+    fn test_unavailable_service() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        assert!(order_srv_records(vec![srv(".", 0, 0)], &mut rng).is_empty());
+    }
+}
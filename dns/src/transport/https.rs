@@ -0,0 +1,200 @@
+use super::{check_response, stream::Stream, with_timeout, BoxFuture, StreamConnector, Transport};
+use crate::{Error, Result};
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    sync::Mutex,
+};
+
+/// Largest response body accepted, which is the largest possible dns message
+const MAX_BODY_SIZE: usize = u16::MAX as usize;
+
+/// Sends questions to a dns server as HTTP/1.1 POST requests over TLS
+pub(crate) struct HttpsTransport {
+    /// Opens TLS streams to the dns server
+    connector: StreamConnector,
+    /// Value of the `Host` header sent with each request
+    host: String,
+    /// Path requests are sent to
+    path: String,
+    /// Stream left open by the last question, reused by the next one if the server allows it
+    stream: Mutex<Option<BufReader<Box<dyn Stream>>>>,
+}
+
+impl HttpsTransport {
+    /// Creates a transport sending requests to `https://{server_name}{path}`, using streams from the given connector
+    pub(crate) fn new(connector: StreamConnector, server_name: &str, path: &str) -> Self {
+        // ipv6 addresses need brackets in the host header
+        let host = match (server_name.contains(':'), connector.address().port()) {
+            (true, 443) => format!("[{server_name}]"),
+            (true, port) => format!("[{server_name}]:{port}"),
+            (false, 443) => server_name.to_string(),
+            (false, port) => format!("{server_name}:{port}"),
+        };
+
+        Self {
+            connector,
+            host,
+            path: path.to_string(),
+            stream: Mutex::new(None),
+        }
+    }
+}
+
+impl Transport for HttpsTransport {
+    fn exchange<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let mut open_stream = self.stream.lock().await;
+            let mut stream = match open_stream.take() {
+                Some(stream) => stream,
+                None => self.connector.connect().await?,
+            };
+
+            let address = self.connector.address();
+            let (response, keep_alive) = with_timeout(address, self.connector.timeout(), async {
+                let mut request = format!(
+                    "POST {} HTTP/1.1\r\n\
+                    Host: {}\r\n\
+                    Content-Type: application/dns-message\r\n\
+                    Accept: application/dns-message\r\n\
+                    Content-Length: {}\r\n\r\n",
+                    self.path,
+                    self.host,
+                    message.len()
+                )
+                .into_bytes();
+                request.extend_from_slice(message);
+                stream.write_all(&request).await?;
+
+                read_response(address, &mut stream).await
+            })
+            .await?;
+            let response = check_response(address, message, response)?;
+
+            if keep_alive {
+                *open_stream = Some(stream);
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+/// Reads a HTTP/1.1 response, returning its body and whether the stream can be used for another request
+async fn read_response(
+    address: SocketAddr,
+    stream: &mut BufReader<Box<dyn Stream>>,
+) -> Result<(Vec<u8>, bool)> {
+    let malformed = |reason: &str| Error::MalformedResponse {
+        server: address,
+        reason: reason.to_string(),
+    };
+
+    let status_line = read_line(stream).await?;
+    let status = status_line
+        .strip_prefix("HTTP/1.1 ")
+        .ok_or_else(|| malformed("invalid HTTP status line"))?;
+    if !status.starts_with("200") {
+        return Err(Error::HttpStatus {
+            server: address,
+            status: status.to_string(),
+        });
+    }
+
+    // only the headers that affect how the body is read matter
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut keep_alive = true;
+    loop {
+        let header = read_line(stream).await?;
+        let Some((name, value)) = header.split_once(':') else {
+            break;
+        };
+        let value = value.trim().to_ascii_lowercase();
+
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => {
+                content_length = Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| malformed("invalid Content-Length"))?,
+                )
+            }
+            "transfer-encoding" => chunked = value.contains("chunked"),
+            "connection" => keep_alive = !value.contains("close"),
+            _ => {}
+        }
+    }
+
+    let body = if chunked {
+        let mut body = Vec::new();
+        loop {
+            let size_line = read_line(stream).await?;
+            let size = usize::from_str_radix(size_line.split(';').next().unwrap_or_default(), 16)
+                .map_err(|_| malformed("invalid chunk size"))?;
+
+            if size > MAX_BODY_SIZE - body.len() {
+                return Err(malformed("response too large"));
+            } else if size == 0 {
+                // skip any trailers, which end with an empty line
+                while !read_line(stream).await?.is_empty() {}
+                break;
+            }
+
+            let start = body.len();
+            body.resize(start + size, 0);
+            stream.read_exact(&mut body[start..]).await?;
+            read_line(stream).await?;
+        }
+
+        body
+    } else if let Some(len) = content_length {
+        if len > MAX_BODY_SIZE {
+            return Err(malformed("response too large"));
+        }
+
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await?;
+        body
+    } else {
+        // without a length the body continues until the stream is closed
+        keep_alive = false;
+
+        let mut body = Vec::new();
+        stream
+            .take(MAX_BODY_SIZE as u64)
+            .read_to_end(&mut body)
+            .await?;
+        body
+    };
+
+    Ok((body, keep_alive))
+}
+
+/// Reads a single line ending in CRLF, without the line ending
+async fn read_line(stream: &mut BufReader<Box<dyn Stream>>) -> Result<String> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_huge_chunk_size() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        server
+            .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\nffffffffffffffff\r\n")
+            .await
+            .unwrap();
+
+        let mut stream = BufReader::new(Box::new(client) as Box<dyn Stream>);
+        let result = read_response("127.0.0.1:443".parse().unwrap(), &mut stream).await;
+        assert!(matches!(result, Err(Error::MalformedResponse { .. })));
+    }
+}
@@ -0,0 +1,517 @@
+mod https;
+mod stream;
+mod tcp;
+mod udp;
+
+pub use stream::add_tls_root_certificate;
+pub(crate) use stream::StreamConnector;
+pub(crate) use tcp::TcpTransport;
+
+use crate::{Error, Result};
+use https::HttpsTransport;
+use std::{
+    fmt,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    str::FromStr,
+    time::Duration,
+};
+use udp::UdpTransport;
+
+/// Future returned by a [Transport], boxed so transports can be used as trait objects
+pub(crate) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Sends encoded dns messages to a single dns server and receives its responses
+pub(crate) trait Transport: Send + Sync {
+    /// Sends a message and waits for the response, giving up after the configured timeout
+    fn exchange<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, Result<Vec<u8>>>;
+}
+
+/// Protocol used to send questions to a dns server
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    /// Plain UDP, retrying over TCP if a response is truncated
+    #[default]
+    Udp,
+    /// Plain TCP
+    Tcp,
+    /// DNS-over-TLS ([RFC 7858](https://datatracker.ietf.org/doc/html/rfc7858)),
+    /// checking the certificate of the dns server is valid for `server_name`
+    Tls { server_name: String },
+    /// DNS-over-HTTPS ([RFC 8484](https://datatracker.ietf.org/doc/html/rfc8484)),
+    /// sending questions to `https://{server_name}{path}`
+    Https { server_name: String, path: String },
+}
+
+impl Protocol {
+    /// Port used by dns servers with this protocol when none is given
+    fn default_port(&self) -> u16 {
+        match self {
+            Protocol::Udp | Protocol::Tcp => 53,
+            Protocol::Tls { .. } => 853,
+            Protocol::Https { .. } => 443,
+        }
+    }
+}
+
+/// A dns server, along with the protocol used to talk to it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Nameserver {
+    /// Address of the dns server
+    pub address: SocketAddr,
+    /// Protocol used to send questions to the dns server
+    pub protocol: Protocol,
+}
+
+impl From<SocketAddr> for Nameserver {
+    /// Creates a dns server which is sent questions over UDP
+    fn from(address: SocketAddr) -> Self {
+        Self {
+            address,
+            protocol: Protocol::Udp,
+        }
+    }
+}
+
+impl FromStr for Nameserver {
+    type Err = Error;
+
+    /// Parses a dns server in the format `[protocol://][name@]ip[:port][/path]`, such as:
+    /// - `10.0.0.53` or `udp://10.0.0.53:5353` for plain UDP
+    /// - `tcp://[fd00::53]` for plain TCP
+    /// - `tls://cloudflare-dns.com@1.1.1.1` for DNS-over-TLS
+    /// - `https://dns.google@8.8.8.8/dns-query` for DNS-over-HTTPS
+    ///
+    /// The name is the one the server's certificate is checked against, and defaults to the ip.
+    /// The port defaults to the standard one for the protocol, and the path to `/dns-query`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Parse(format!("invalid dns server `{s}`"));
+
+        let (scheme, rest) = s.split_once("://").unwrap_or(("udp", s));
+        let (server, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/dns-query"),
+        };
+        let (server_name, address) = match server.split_once('@') {
+            Some((server_name, address)) => (Some(server_name.to_string()), address),
+            None => (None, server),
+        };
+
+        // ipv6 addresses can be given with or without brackets when there's no port
+        let ip = IpAddr::from_str(address.trim_start_matches('[').trim_end_matches(']'));
+        let server_name = || server_name.clone().unwrap_or_else(|| ip_name(address));
+
+        let protocol = match scheme.to_ascii_lowercase().as_str() {
+            "udp" if path == "/dns-query" => Protocol::Udp,
+            "tcp" if path == "/dns-query" => Protocol::Tcp,
+            "tls" if path == "/dns-query" => Protocol::Tls {
+                server_name: server_name(),
+            },
+            "https" => Protocol::Https {
+                server_name: server_name(),
+                path: path.to_string(),
+            },
+            _ => return Err(invalid()),
+        };
+
+        let address = match ip {
+            Ok(ip) => SocketAddr::new(ip, protocol.default_port()),
+            Err(_) => SocketAddr::from_str(address).map_err(|_| invalid())?,
+        };
+
+        Ok(Self { address, protocol })
+    }
+}
+
+/// Name used for a dns server given as just an address, which is the ip without any brackets or port
+fn ip_name(address: &str) -> String {
+    match SocketAddr::from_str(address) {
+        Ok(address) => address.ip().to_string(),
+        Err(_) => address
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+    }
+}
+
+impl fmt::Display for Nameserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.protocol {
+            Protocol::Udp => write!(f, "{}", self.address),
+            Protocol::Tcp => write!(f, "tcp://{}", self.address),
+            Protocol::Tls { server_name } => write!(f, "tls://{server_name}@{}", self.address),
+            Protocol::Https { server_name, path } => {
+                write!(f, "https://{server_name}@{}{path}", self.address)
+            }
+        }
+    }
+}
+
+impl Nameserver {
+    /// Creates the transport used to send questions to this dns server
+    pub(crate) async fn transport(
+        &self,
+        timeout: Duration,
+        attempts: usize,
+    ) -> Result<Box<dyn Transport>> {
+        Ok(match &self.protocol {
+            Protocol::Udp => Box::new(UdpTransport::new(self.address, timeout, attempts).await?),
+            Protocol::Tcp => Box::new(TcpTransport::new(StreamConnector::tcp(
+                self.address,
+                timeout,
+            ))),
+            Protocol::Tls { server_name } => Box::new(TcpTransport::new(StreamConnector::tls(
+                self.address,
+                timeout,
+                server_name,
+                Vec::new(),
+            )?)),
+            Protocol::Https { server_name, path } => Box::new(HttpsTransport::new(
+                StreamConnector::tls(
+                    self.address,
+                    timeout,
+                    server_name,
+                    vec![b"http/1.1".to_vec()],
+                )?,
+                server_name,
+                path,
+            )),
+        })
+    }
+}
+
+/// Checks a response is for the given question, by making sure it has the same id and asks the same questions.
+/// Names are compared ignoring case, as some dns servers don't preserve it
+pub(crate) fn is_response_to(question: &[u8], response: &[u8]) -> bool {
+    // header is 12 bytes, starting with the id, then flags (where the first bit marks a response),
+    // then the number of questions
+    if question.len() < 12 || response.len() < 12 {
+        return false;
+    }
+    if question[0..2] != response[0..2]
+        || response[2] & 0x80 == 0
+        || question[4..6] != response[4..6]
+    {
+        return false;
+    }
+
+    let mut offset = 12;
+    for _ in 0..u16::from_be_bytes([question[4], question[5]]) {
+        // each question is a name made of length prefixed labels ending with an empty label,
+        // followed by 2 bytes of type and 2 of class
+        loop {
+            let Some(&len) = question.get(offset) else {
+                return false;
+            };
+            let label = offset..offset + len as usize + 1;
+            match (question.get(label.clone()), response.get(label)) {
+                (Some(question), Some(response)) if question.eq_ignore_ascii_case(response) => {}
+                _ => return false,
+            }
+
+            offset += len as usize + 1;
+            if len == 0 {
+                break;
+            }
+        }
+
+        let fields = offset..offset + 4;
+        match (question.get(fields.clone()), response.get(fields)) {
+            (Some(question), Some(response)) if question == response => offset += 4,
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// Fails if a response from a stream isn't for the given question.
+/// Streams only ever carry responses to questions sent on them, so any mismatch means the dns server is misbehaving
+pub(crate) fn check_response(
+    address: SocketAddr,
+    question: &[u8],
+    response: Vec<u8>,
+) -> Result<Vec<u8>> {
+    if is_response_to(question, &response) {
+        Ok(response)
+    } else {
+        Err(Error::MalformedResponse {
+            server: address,
+            reason: "response doesn't match question".to_string(),
+        })
+    }
+}
+
+/// Runs an io future, failing if the dns server takes too long to respond
+pub(crate) async fn with_timeout<T>(
+    address: SocketAddr,
+    timeout: Duration,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::time::timeout(timeout, future)
+        .await
+        .map_err(|_| Error::Timeout(address))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Resolver, ResolverConfig, Zone};
+    use std::{
+        net::Ipv4Addr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, UdpSocket},
+    };
+    use tokio_rustls::{rustls, TlsAcceptor};
+
+    /// Starts a TLS server with a self signed certificate for `localhost`, trusted by all transports
+    async fn tls_server() -> (TcpListener, TlsAcceptor) {
+        let certificate =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let der = certificate.serialize_der().unwrap();
+        add_tls_root_certificate(&der).unwrap();
+
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(der)],
+                rustls::PrivateKey(certificate.serialize_private_key_der()),
+            )
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        (listener, TlsAcceptor::from(Arc::new(config)))
+    }
+
+    /// Looks up a domain using only the given dns server
+    async fn lookup_with(nameserver: &str, domain: &str) -> crate::Result<IpAddr> {
+        let resolver = Resolver {
+            config: ResolverConfig {
+                nameservers: vec![nameserver.parse()?],
+                timeout: Duration::from_secs(2),
+                ..Default::default()
+            },
+            fallback_servers: Vec::new(),
+            ..Default::default()
+        };
+
+        Ok(resolver.lookup(domain, 25565).await?.ip)
+    }
+
+    #[test]
+    fn test_parse_nameserver() {
+        let nameserver: Nameserver = "tls://cloudflare-dns.com@1.1.1.1".parse().unwrap();
+        assert_eq!(nameserver.address, "1.1.1.1:853".parse().unwrap());
+        assert_eq!(
+            nameserver.protocol,
+            Protocol::Tls {
+                server_name: "cloudflare-dns.com".to_string()
+            }
+        );
+
+        let nameserver: Nameserver = "https://[2001:4860:4860::8888]".parse().unwrap();
+        assert_eq!(
+            nameserver.address,
+            "[2001:4860:4860::8888]:443".parse().unwrap()
+        );
+        assert_eq!(
+            nameserver.protocol,
+            Protocol::Https {
+                server_name: "2001:4860:4860::8888".to_string(),
+                path: "/dns-query".to_string()
+            }
+        );
+
+        let nameserver: Nameserver = "10.0.0.53:5353".parse().unwrap();
+        assert_eq!(nameserver, Nameserver::from(nameserver.address));
+        assert_eq!(nameserver.to_string(), "10.0.0.53:5353");
+
+        assert!("tcp://dns.example.com".parse::<Nameserver>().is_err());
+        assert!("quic://10.0.0.53".parse::<Nameserver>().is_err());
+    }
+
+    #[test]
+    fn test_is_response_to() {
+        // question for `Example.com` A, with the id 0x1234
+        let question = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07Example\x03com\x00\x00\x01\x00\x01";
+        let response = Zone::new()
+            .a("example.com", Ipv4Addr::LOCALHOST)
+            .respond(question)
+            .unwrap();
+        assert!(is_response_to(question, &response));
+
+        // names are compared ignoring case
+        let mut lowercase = response.clone();
+        lowercase[13] = b'e';
+        assert!(is_response_to(question, &lowercase));
+
+        let mut wrong_id = response.clone();
+        wrong_id[1] = 0x35;
+        assert!(!is_response_to(question, &wrong_id));
+
+        let mut wrong_type = response.clone();
+        wrong_type[26] = 28;
+        assert!(!is_response_to(question, &wrong_type));
+
+        // a question isn't a response to itself
+        assert!(!is_response_to(question, question));
+        assert!(!is_response_to(question, &response[..20]));
+    }
+
+    #[tokio::test]
+    async fn test_udp_ignores_mismatched_responses() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let spoofed_zone = Zone::new().a("spoofed.test", Ipv4Addr::new(6, 6, 6, 6));
+            let zone = Zone::new().a("spoofed.test", Ipv4Addr::new(10, 0, 0, 3));
+
+            loop {
+                let mut question = [0; 512];
+                let (len, client) = socket.recv_from(&mut question).await.unwrap();
+                let question = &question[..len];
+                let spoofed = spoofed_zone.respond(question).unwrap();
+
+                // response from the wrong address
+                spoofer.send_to(&spoofed, client).await.unwrap();
+
+                // response with the wrong id
+                let mut wrong_id = spoofed.clone();
+                wrong_id[0] ^= 0xff;
+                socket.send_to(&wrong_id, client).await.unwrap();
+
+                // response for a different name
+                let mut wrong_name = spoofed.clone();
+                wrong_name[13] ^= 0x01;
+                socket.send_to(&wrong_name, client).await.unwrap();
+
+                let response = zone.respond(question).unwrap();
+                socket.send_to(&response, client).await.unwrap();
+            }
+        });
+
+        let ip = lookup_with(&format!("udp://{address}"), "spoofed.test")
+            .await
+            .unwrap();
+        assert_eq!(ip, Ipv4Addr::new(10, 0, 0, 3));
+    }
+
+    #[tokio::test]
+    async fn test_tls_transport() {
+        let (listener, acceptor) = tls_server().await;
+        let address = listener.local_addr().unwrap();
+
+        let zone = Zone::new().a("dot.test", Ipv4Addr::new(10, 0, 0, 1));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let connections_clone = connections.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                let zone = zone.clone();
+                connections_clone.fetch_add(1, Ordering::Relaxed);
+
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+
+                    while let Ok(len) = stream.read_u16().await {
+                        let mut question = vec![0; len as usize];
+                        stream.read_exact(&mut question).await.unwrap();
+
+                        let response = zone.respond(&question).unwrap();
+                        stream.write_u16(response.len() as u16).await.unwrap();
+                        stream.write_all(&response).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        let ip = lookup_with(&format!("tls://localhost@{address}"), "dot.test")
+            .await
+            .unwrap();
+        assert_eq!(ip, Ipv4Addr::new(10, 0, 0, 1));
+
+        // the SRV and A questions should both be sent over the same connection
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+
+        // certificate doesn't match the name, so the connection should be refused
+        let error = lookup_with(&format!("tls://dns.example.com@{address}"), "bad-dot.test")
+            .await
+            .unwrap_err();
+        let Error::AllServersFailed { errors, .. } = error else {
+            panic!("unexpected error {error:?}");
+        };
+        assert!(
+            matches!(&errors[0].1, Error::Io(e) if e.kind() == std::io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_https_transport() {
+        let (listener, acceptor) = tls_server().await;
+        let address = listener.local_addr().unwrap();
+
+        let zone = Zone::new().a("doh.test", Ipv4Addr::new(10, 0, 0, 2));
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(acceptor.accept(stream).await.unwrap());
+
+            loop {
+                // read request line and headers, only caring about the length of the body
+                let mut request_line = String::new();
+                if stream.read_line(&mut request_line).await.unwrap() == 0 {
+                    break;
+                }
+                assert_eq!(request_line, "POST /custom-path HTTP/1.1\r\n");
+
+                let mut len = 0;
+                loop {
+                    let mut header = String::new();
+                    stream.read_line(&mut header).await.unwrap();
+                    match header.to_ascii_lowercase().split_once(':') {
+                        Some(("content-length", value)) => len = value.trim().parse().unwrap(),
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+
+                let mut question = vec![0; len];
+                stream.read_exact(&mut question).await.unwrap();
+
+                // send the response in chunks, to check they're put back together properly
+                let response = zone.respond(&question).unwrap();
+                let (first, second) = response.split_at(5);
+                let mut http = b"HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ntransfer-encoding: chunked\r\n\r\n".to_vec();
+                for chunk in [first, second] {
+                    http.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+                    http.extend_from_slice(chunk);
+                    http.extend_from_slice(b"\r\n");
+                }
+                http.extend_from_slice(b"0\r\n\r\n");
+                stream.write_all(&http).await.unwrap();
+            }
+        });
+
+        let ip = lookup_with(
+            &format!("https://localhost@{address}/custom-path"),
+            "doh.test",
+        )
+        .await
+        .unwrap();
+        assert_eq!(ip, Ipv4Addr::new(10, 0, 0, 2));
+    }
+}
@@ -0,0 +1,119 @@
+use super::with_timeout;
+use crate::{Error, Result};
+use lazy_static::lazy_static;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{self, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+lazy_static! {
+    /// Certificates trusted when connecting to dns servers over TLS, starting with the [Mozilla root certificates](https://wiki.mozilla.org/CA/Included_Certificates)
+    static ref TLS_ROOTS: RwLock<RootCertStore> = {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+
+        RwLock::new(roots)
+    };
+}
+
+/// Trusts an extra certificate (in DER format) when connecting to dns servers over TLS or HTTPS,
+/// such as the root certificate of a private certificate authority
+pub fn add_tls_root_certificate(der: &[u8]) -> Result<()> {
+    TLS_ROOTS
+        .write()
+        .unwrap()
+        .add(&rustls::Certificate(der.to_vec()))
+        .map_err(|e| Error::Parse(format!("invalid certificate: {e}")))
+}
+
+/// Any stream that can be used to talk to a dns server
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Opens streams to a single dns server, which may be wrapped in TLS
+pub(crate) struct StreamConnector {
+    /// Address of the dns server
+    address: SocketAddr,
+    /// How long to wait for the dns server to respond
+    timeout: Duration,
+    /// Connector and expected name of the dns server, if TLS is used
+    tls: Option<(TlsConnector, ServerName)>,
+}
+
+impl StreamConnector {
+    /// Creates a connector for plain TCP streams
+    pub(crate) fn tcp(address: SocketAddr, timeout: Duration) -> Self {
+        Self {
+            address,
+            timeout,
+            tls: None,
+        }
+    }
+
+    /// Creates a connector for TLS streams, checking the certificate of the dns server matches `server_name`
+    /// and offering the given ALPN protocols
+    pub(crate) fn tls(
+        address: SocketAddr,
+        timeout: Duration,
+        server_name: &str,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> Result<Self> {
+        let server_name = ServerName::try_from(server_name)
+            .map_err(|_| Error::Parse(format!("invalid tls server name `{server_name}`")))?;
+
+        let mut config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(TLS_ROOTS.read().unwrap().clone())
+            .with_no_client_auth();
+        config.alpn_protocols = alpn_protocols;
+
+        Ok(Self {
+            address,
+            timeout,
+            tls: Some((TlsConnector::from(Arc::new(config)), server_name)),
+        })
+    }
+
+    /// Address of the dns server
+    pub(crate) fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// How long to wait for the dns server to respond
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Opens a new stream to the dns server
+    pub(crate) async fn connect(&self) -> Result<BufReader<Box<dyn Stream>>> {
+        with_timeout(self.address, self.timeout, async {
+            let stream = TcpStream::connect(self.address).await?;
+
+            let stream: Box<dyn Stream> = match &self.tls {
+                Some((connector, server_name)) => {
+                    Box::new(connector.connect(server_name.clone(), stream).await?)
+                }
+                None => Box::new(stream),
+            };
+
+            Ok(BufReader::new(stream))
+        })
+        .await
+    }
+}
@@ -0,0 +1,63 @@
+use super::{check_response, stream::Stream, with_timeout, BoxFuture, StreamConnector, Transport};
+use crate::Result;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    sync::Mutex,
+};
+
+/// Sends questions to a dns server over TCP, or TLS when created with a TLS connector.
+///
+/// Each message is prefixed with its length as described in
+/// [RFC 1035 section 4.2.2](https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2),
+/// which DNS-over-TLS also uses
+pub(crate) struct TcpTransport {
+    /// Opens streams to the dns server
+    connector: StreamConnector,
+    /// Stream left open by the last question, reused by the next one
+    stream: Mutex<Option<BufReader<Box<dyn Stream>>>>,
+}
+
+impl TcpTransport {
+    /// Creates a transport using streams from the given connector, which aren't opened until needed
+    pub(crate) fn new(connector: StreamConnector) -> Self {
+        Self {
+            connector,
+            stream: Mutex::new(None),
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn exchange<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let mut open_stream = self.stream.lock().await;
+            let mut stream = match open_stream.take() {
+                Some(stream) => stream,
+                None => self.connector.connect().await?,
+            };
+
+            let response =
+                with_timeout(self.connector.address(), self.connector.timeout(), async {
+                    let mut request = Vec::with_capacity(message.len() + 2);
+                    request.extend_from_slice(&(message.len() as u16).to_be_bytes());
+                    request.extend_from_slice(message);
+                    stream.write_all(&request).await?;
+
+                    // read length of response, then the response itself
+                    let len = stream.read_u16().await?;
+
+                    let mut response = vec![0; len as usize];
+                    stream.read_exact(&mut response).await?;
+
+                    Ok(response)
+                })
+                .await?;
+            let response = check_response(self.connector.address(), message, response)?;
+
+            // only keep streams that are known to still be working
+            *open_stream = Some(stream);
+
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,88 @@
+use super::{is_response_to, BoxFuture, Transport};
+use crate::{connection::EDNS_PAYLOAD_SIZE, Error, Result};
+use log::debug;
+use std::{net::SocketAddr, time::Duration};
+use tokio::{net::UdpSocket, time::Instant};
+
+/// Sends questions to a dns server over UDP
+pub(crate) struct UdpTransport {
+    /// Address of the dns server
+    address: SocketAddr,
+    /// Socket connected to the dns server, reused for every question
+    socket: UdpSocket,
+    /// How long to wait for the dns server to respond
+    timeout: Duration,
+    /// How many times to send a question before giving up
+    attempts: usize,
+}
+
+impl UdpTransport {
+    /// Creates a new socket connected to the given dns server
+    pub(crate) async fn new(
+        address: SocketAddr,
+        timeout: Duration,
+        attempts: usize,
+    ) -> Result<Self> {
+        // make sure the socket matches the family of the dns server
+        let bind_address = match address {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+
+        let socket = UdpSocket::bind(bind_address).await?;
+        socket.connect(address).await?;
+
+        Ok(Self {
+            address,
+            socket,
+            timeout,
+            attempts: attempts.max(1),
+        })
+    }
+}
+
+impl UdpTransport {
+    /// Waits for a response to the given question until the deadline passes, ignoring any datagrams
+    /// that aren't a response to it (such as late responses to earlier questions, or spoofed responses)
+    async fn receive(&self, question: &[u8], deadline: Instant) -> Result<Option<Vec<u8>>> {
+        loop {
+            // read into buffer and then keep only the part that was filled
+            let mut response = vec![0; EDNS_PAYLOAD_SIZE as usize];
+            let (len, source) =
+                match tokio::time::timeout_at(deadline, self.socket.recv_from(&mut response)).await
+                {
+                    Ok(received) => received?,
+                    Err(_) => return Ok(None),
+                };
+            response.truncate(len);
+
+            if source != self.address {
+                debug!("ignoring response from {source}, expected {}", self.address);
+            } else if !is_response_to(question, &response) {
+                debug!("ignoring response from {source} that doesn't match question");
+            } else {
+                return Ok(Some(response));
+            }
+        }
+    }
+}
+
+impl Transport for UdpTransport {
+    /// Sends a message over UDP, returning the (possibly truncated) response.
+    ///
+    /// As UDP is unreliable, the message is sent again if no matching response arrives in time
+    fn exchange<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            for attempt in 1..=self.attempts {
+                self.socket.send(message).await?;
+
+                match self.receive(message, Instant::now() + self.timeout).await? {
+                    Some(response) => return Ok(response),
+                    None => debug!("attempt {attempt} to reach {} timed out", self.address),
+                }
+            }
+
+            Err(Error::Timeout(self.address))
+        })
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{config::ServerInfo, status::ServerStatus, status::StatusEntry, Status};
+use axum::{extract::Path, http::StatusCode, Json};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// A server and its last known status, as returned by the JSON api
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct ApiServer {
+    /// Slug of the server, used to fetch it on its own
+    id: String,
+    /// Name shown for the server
+    name: String,
+    /// Address of the server, as configured
+    address: String,
+    /// Description shown under the server's name
+    description: Option<String>,
+    /// Whether the server responded the last time it was checked
+    online: bool,
+    /// Number of players online, if the server is online
+    players_online: Option<u32>,
+    /// Number of players the server can hold, if the server is online
+    players_maximum: Option<u32>,
+    /// Version of minecraft the server is running, if the server is online
+    version: Option<String>,
+    /// Message of the day, if the server is online
+    motd: Option<String>,
+    /// Full status of the server, including the raw response from the server
+    #[serde(flatten)]
+    status: StatusEntry,
+}
+
+impl ApiServer {
+    /// Combines a server with its status
+    fn new(server: &ServerInfo, entry: &StatusEntry) -> Self {
+        let response = match &entry.status {
+            ServerStatus::Online(response) => Some(response),
+            _ => None,
+        };
+
+        Self {
+            id: server.slug.clone(),
+            name: server.name.clone(),
+            address: server.address.clone(),
+            description: server.description.clone(),
+            online: response.is_some(),
+            players_online: response.map(|r| r.players_online()),
+            players_maximum: response.map(|r| r.players_maximum()),
+            version: response.map(|r| r.version().to_string()),
+            motd: response.map(|r| r.motd().to_string()),
+            status: entry.clone(),
+        }
+    }
+}
+
+/// Error returned by the JSON api
+#[derive(Debug, Serialize)]
+pub(crate) struct ApiError {
+    /// What went wrong
+    error: String,
+}
+
+/// Serves the status of all servers as JSON, in the order they're configured to be shown
+pub(crate) async fn serve_all_servers(
+    servers: Arc<Vec<ServerInfo>>,
+    status: Status,
+) -> Json<Vec<ApiServer>> {
+    let read = status.read().unwrap();
+
+    Json(
+        servers
+            .iter()
+            .filter_map(|server| Some(ApiServer::new(server, read.get(&server.slug)?)))
+            .collect(),
+    )
+}
+
+/// Serves the status of a single server as JSON, found by its slug
+pub(crate) async fn serve_single_server(
+    Path(id): Path<String>,
+    servers: Arc<Vec<ServerInfo>>,
+    status: Status,
+) -> Result<Json<ApiServer>, (StatusCode, Json<ApiError>)> {
+    let read = status.read().unwrap();
+
+    servers
+        .iter()
+        .find(|server| server.slug == id)
+        .and_then(|server| Some(Json(ApiServer::new(server, read.get(&server.slug)?))))
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiError {
+                    error: format!("no server with id `{id}`"),
+                }),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{java_response, server_info, shared_status, status_entry, LAST_CHECKED};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_api() {
+        let servers = Arc::new(vec![server_info("survival"), server_info("broken")]);
+        let status = shared_status([
+            (
+                "survival",
+                status_entry(ServerStatus::Online(java_response())),
+            ),
+            (
+                "broken",
+                StatusEntry {
+                    last_error: Some("NXDOMAIN".to_string()),
+                    ..status_entry(ServerStatus::DnsFailure {
+                        reason: "NXDOMAIN".to_string(),
+                    })
+                },
+            ),
+        ]);
+
+        let Json(all) = serve_all_servers(servers.clone(), status.clone()).await;
+        let all = serde_json::to_value(all).unwrap();
+        assert_eq!(all[0]["id"], "survival");
+        assert_eq!(all[0]["online"], true);
+        assert_eq!(all[0]["players_online"], 2);
+        assert_eq!(all[0]["players_maximum"], 20);
+        assert_eq!(all[0]["version"], "1.20.2");
+        assert_eq!(all[0]["motd"], "A minecraft server");
+        assert_eq!(all[0]["state"], "online");
+        assert_eq!(all[0]["edition"], "java");
+        assert_eq!(all[0]["last_checked"], LAST_CHECKED);
+        assert_eq!(all[0]["last_error"], json!(null));
+        assert_eq!(all[0]["latency_ms"], 35);
+        assert_eq!(all[1]["id"], "broken");
+        assert_eq!(all[1]["online"], false);
+        assert_eq!(all[1]["players_online"], json!(null));
+        assert_eq!(all[1]["state"], "dns_failure");
+        assert_eq!(all[1]["last_error"], "NXDOMAIN");
+
+        let Json(single) =
+            serve_single_server(Path("broken".to_string()), servers.clone(), status.clone())
+                .await
+                .unwrap();
+        assert_eq!(serde_json::to_value(single).unwrap(), all[1]);
+
+        let (code, _) = serve_single_server(Path("missing".to_string()), servers, status)
+            .await
+            .unwrap_err();
+        assert_eq!(code, StatusCode::NOT_FOUND);
+    }
+}
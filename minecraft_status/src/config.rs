@@ -1,139 +1,1024 @@
+use crate::{
+    config_file::{ConfigFile, ServerEntry},
+    status::Edition,
+};
 use anyhow::{anyhow, Result};
-use dns::domain_lookup;
-use log::{debug, warn};
-use std::{net::IpAddr, str::FromStr, time::Duration};
+use dns::{ConfigSource, HostOverride, IpPreference, Lookup, Nameserver, Resolver};
+use log::{debug, info};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 /// Default refresh interval (60 seconds)
 const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
-/// Default port (25565)
-const DEFAULT_PORT: u16 = 25565;
+/// Default port for dns servers (53)
+const DEFAULT_DNS_PORT: u16 = 53;
+/// How long to wait before looking up a server again after the first failure (5 seconds), doubling after each one
+const INITIAL_DNS_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Longest time to wait before looking up a server again after failures (10 minutes)
+const MAX_DNS_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Slugs which can't be used for servers, as their paths are used for other pages
+const RESERVED_SLUGS: [&str; 2] = ["api", "metrics"];
 
 /// Stores configuration loaded at program start
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct Config {
-    /// How often to refresh data
+    /// How often to refresh data, for servers that don't set their own refresh interval
     pub(crate) refresh_interval: Duration,
+    /// Resolver used to look up server domains
+    pub(crate) resolver: Resolver,
     /// Servers to check
     pub(crate) servers: Vec<Server>,
 }
 
 impl Config {
-    /// Creates a config from set env vars
-    pub fn from_env_vars() -> Result<Self> {
-        // get refresh interval from end var, try and parse it, and use default if any steps fail
-        let refresh_interval = {
-            let refresh_interval_str = std::env::var("REFRESH_INTERVAL").ok();
-
-            // 3 cases we care about:
-            // REFRESH_INTERVAL has value and it's valid duration -> use that duration
-            // REFRESH_INTERVAL has value but not a valid duration -> use default duration and log invalid
-            // REFRESH_INTERVAL has no value -> use default
-            match refresh_interval_str {
-                Some(duration_str) => match parse_duration::parse(&duration_str) {
-                    Ok(duration) => duration,
-                    Err(_) => {
-                        warn!("env var `REFRESH_INTERVAL` has invalid value `{duration_str}`");
-                        DEFAULT_REFRESH_INTERVAL
-                    }
-                },
-                _ => DEFAULT_REFRESH_INTERVAL,
-            }
+    /// Creates a config from the config file (if there is one) and set env vars
+    pub fn load() -> Result<Self> {
+        Self::from_file(ConfigFile::load()?, |name| std::env::var(name).ok())
+    }
+
+    /// Creates a config from the given config file, with any set env vars taking priority over its values.
+    ///
+    /// Env vars are found with `env`, which gives the value of an env var if it's set
+    pub fn from_file(file: ConfigFile, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        // get refresh interval from env var or config file, using default if missing
+        let refresh_interval = match setting(&env, "REFRESH_INTERVAL", file.refresh_interval) {
+            Some((source, duration_str)) => parse_duration_setting(&source, &duration_str)?,
+            None => DEFAULT_REFRESH_INTERVAL,
+        };
+
+        // get ip preference from env var or config file, using default if missing
+        let ip_preference = match setting(&env, "IP_PREFERENCE", file.ip_preference) {
+            Some((source, preference_str)) => preference_str
+                .parse()
+                .map_err(|_| anyhow!("{source} has invalid value `{preference_str}`"))?,
+            None => IpPreference::default(),
         };
 
-        let server = std::env::var("SERVER").map_err(|_| anyhow!("env var `SERVER` is missing"))?;
+        let mut resolver = resolver_from_settings(
+            &env,
+            ip_preference,
+            file.dns_port,
+            file.dns_servers,
+            file.dns_fallback_servers,
+            file.dns_timeout,
+        )?;
+
+        if let Some(static_hosts) = env("STATIC_HOSTS") {
+            resolver.static_hosts = parse_static_hosts(&static_hosts)?;
+        } else if let Some(static_hosts) = file.static_hosts {
+            resolver.static_hosts = static_hosts
+                .into_iter()
+                .map(|(name, host)| Ok((name, host.trim().parse()?)))
+                .collect::<Result<_>>()?;
+        }
+
+        // servers from the env var replace any in the config file, rather than being added to them
+        let entries: Vec<_> = match env("SERVER") {
+            Some(servers) => servers.split(',').map(ServerEntry::new).collect(),
+            None => file.servers,
+        };
+        if entries.is_empty() {
+            return Err(anyhow!(
+                "no servers to check, set env var `SERVER` or add servers to the config file"
+            ));
+        }
+
+        let mut servers: Vec<Server> = Vec::new();
+        for entry in entries {
+            let edition = entry.edition.unwrap_or_default();
+            let mut server = Server::parse(&entry.address, edition)?;
+            server.refresh_interval = match &entry.refresh_interval {
+                Some(duration_str) => parse_duration_setting(
+                    &format!("refresh interval for server `{}`", entry.address),
+                    duration_str,
+                )?,
+                None => refresh_interval,
+            };
+            server.info.apply(&entry)?;
+
+            // each slug is used as a path, so must only lead to one server
+            if let Some(other) = servers
+                .iter()
+                .find(|other| other.info.slug == server.info.slug)
+            {
+                return Err(anyhow!(
+                    "servers `{}` and `{}` have the same slug `{}`",
+                    other.server,
+                    server.server,
+                    server.info.slug
+                ));
+            }
+
+            servers.push(server);
+        }
 
-        let servers = server
-            .split(",")
-            .map(Server::parse)
-            .collect::<Result<Vec<_>>>()?;
+        // sorting is stable, so servers with the same order keep the order they were given in
+        servers.sort_by_key(|server| server.info.order);
 
         Ok(Self {
             refresh_interval,
+            resolver,
             servers,
         })
     }
 }
 
+/// Finds the value of a setting from its env var, or from the config file if the env var isn't set.
+///
+/// The value is returned along with where it came from, for use in log and error messages
+fn setting(
+    env: &impl Fn(&str) -> Option<String>,
+    env_var: &str,
+    file_value: Option<String>,
+) -> Option<(String, String)> {
+    match env(env_var) {
+        Some(value) => Some((format!("env var `{env_var}`"), value)),
+        None => file_value.map(|value| {
+            (
+                format!("config file setting `{}`", env_var.to_ascii_lowercase()),
+                value,
+            )
+        }),
+    }
+}
+
+/// Parses a duration, which must be more than zero
+fn parse_duration_setting(source: &str, duration_str: &str) -> Result<Duration> {
+    match parse_duration::parse(duration_str) {
+        Ok(duration) if duration > Duration::ZERO => Ok(duration),
+        Ok(_) => Err(anyhow!("{source} must be more than zero")),
+        Err(_) => Err(anyhow!("{source} has invalid value `{duration_str}`")),
+    }
+}
+
+/// Creates a resolver using the dns servers from the OS, with any options from env vars or the config file
+/// applied on top
+fn resolver_from_settings(
+    env: &impl Fn(&str) -> Option<String>,
+    preference: IpPreference,
+    port: Option<u16>,
+    servers: Option<Vec<String>>,
+    fallback_servers: Option<Vec<String>>,
+    timeout: Option<String>,
+) -> Result<Resolver> {
+    let mut resolver = Resolver {
+        preference,
+        ..Default::default()
+    };
+
+    // port is used for any dns servers that don't have one given explicitly, other than the default fallback servers
+    // as they're public servers on the standard port
+    let port = match setting(env, "DNS_PORT", port.map(|port| port.to_string())) {
+        Some((source, port_str)) => Some(
+            port_str
+                .parse()
+                .map_err(|_| anyhow!("{source} has invalid value `{port_str}`"))?,
+        ),
+        _ => None,
+    };
+
+    match setting(env, "DNS_SERVERS", servers.map(|servers| servers.join(","))) {
+        Some((source, servers)) => {
+            resolver.config.nameservers =
+                parse_dns_servers(&source, &servers, port.unwrap_or(DEFAULT_DNS_PORT))?;
+            resolver.config.source = ConfigSource::Manual;
+        }
+        _ => {
+            if let Some(port) = port {
+                set_default_port(&mut resolver.config.nameservers, port);
+            }
+        }
+    }
+
+    // an empty list in the config file disables fallback, like `none` does for the env var
+    let fallback_servers = fallback_servers.map(|servers| servers.join(","));
+    if let Some((source, servers)) = setting(env, "DNS_FALLBACK_SERVERS", fallback_servers) {
+        resolver.fallback_servers =
+            parse_dns_servers(&source, &servers, port.unwrap_or(DEFAULT_DNS_PORT))?;
+    }
+
+    if let Some((source, timeout_str)) = setting(env, "DNS_TIMEOUT", timeout) {
+        resolver.config.timeout = parse_duration_setting(&source, &timeout_str)?;
+    }
+
+    Ok(resolver)
+}
+
+/// Changes the port of any dns servers from the OS which are using the default port. Servers given their own port
+/// (such as by systemd-resolved links) keep it
+fn set_default_port(nameservers: &mut [Nameserver], port: u16) {
+    nameservers
+        .iter_mut()
+        .filter(|server| server.address.port() == DEFAULT_DNS_PORT)
+        .for_each(|server| server.address.set_port(port));
+}
+
+/// Parses a list of dns servers separated by commas, in the format `ip[:port]` or any format supported by
+/// [Nameserver] (such as `tls://name@ip`). `none` gives an empty list
+fn parse_dns_servers(source: &str, servers: &str, port: u16) -> Result<Vec<Nameserver>> {
+    if servers.trim().eq_ignore_ascii_case("none") {
+        return Ok(Vec::new());
+    }
+
+    servers
+        .split(',')
+        .map(str::trim)
+        .filter(|server| !server.is_empty())
+        .map(|server| match IpAddr::from_str(server) {
+            Ok(ip) => Ok(SocketAddr::new(ip, port).into()),
+            Err(_) => Nameserver::from_str(server)
+                .map_err(|_| anyhow!("{source} has invalid server `{server}`")),
+        })
+        .collect()
+}
+
+/// Parses static hosts in the format `name=ip[:port]`, with multiple entries separated by commas
+fn parse_static_hosts(static_hosts: &str) -> Result<HashMap<String, HostOverride>> {
+    static_hosts
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (name, host) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("env var `STATIC_HOSTS` has invalid entry `{entry}`"))?;
+
+            Ok((name.trim().to_string(), host.trim().parse()?))
+        })
+        .collect()
+}
+
+/// How a server is shown on the status pages
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ServerInfo {
+    /// Address of the server, as configured
+    pub(crate) address: String,
+    /// Name shown for the server, which is its address unless one is configured
+    pub(crate) name: String,
+    /// Path the server's status page is served on
+    pub(crate) slug: String,
+    /// Description shown under the server's name
+    pub(crate) description: Option<String>,
+    /// Link to the server's website
+    pub(crate) website: Option<String>,
+    /// Link to the server's discord
+    pub(crate) discord: Option<String>,
+    /// Position of the server on the main page, with lower values shown first
+    pub(crate) order: i32,
+}
+
+impl ServerInfo {
+    /// Creates the info for a server without any configured display settings, based on its address
+    fn new(server: &str) -> Self {
+        Self {
+            address: server.to_string(),
+            name: server.to_string(),
+            slug: slugify(server),
+            description: None,
+            website: None,
+            discord: None,
+            order: 0,
+        }
+    }
+
+    /// Applies the display settings from a config file entry, checking the slug (including the default one) and
+    /// links are valid
+    fn apply(&mut self, entry: &ServerEntry) -> Result<()> {
+        if let Some(slug) = &entry.slug {
+            if !is_valid_slug(slug) || slugify(slug) != *slug {
+                return Err(anyhow!(
+                    "server `{}` has invalid slug `{slug}`, which can only contain letters, numbers, `-`, `_` and `.` and can't be only dots",
+                    entry.address
+                ));
+            }
+            self.slug = slug.clone();
+        } else if !is_valid_slug(&self.slug) {
+            // the default slug comes from the address, which may not have anything usable in it (such as `::`)
+            return Err(anyhow!(
+                "server `{}` doesn't give a valid slug, so needs one set in the config file",
+                entry.address
+            ));
+        }
+
+        // slugs share paths with the rest of the site, so can't be used for anything else
+        if RESERVED_SLUGS.contains(&self.slug.as_str()) {
+            return Err(anyhow!(
+                "server `{}` has slug `{}`, which is reserved, so needs a different one set in the config file",
+                entry.address,
+                self.slug
+            ));
+        }
+
+        // links are put straight into the page, so make sure they can't run scripts
+        for link in [&entry.website, &entry.discord].into_iter().flatten() {
+            if !link.starts_with("https://") && !link.starts_with("http://") {
+                return Err(anyhow!(
+                    "server `{}` has invalid link `{link}`, which must start with `https://` or `http://`",
+                    entry.address
+                ));
+            }
+        }
+
+        if let Some(name) = &entry.name {
+            self.name = name.clone();
+        }
+        self.description = entry.description.clone();
+        self.website = entry.website.clone();
+        self.discord = entry.discord.clone();
+        self.order = entry.order.unwrap_or_default();
+
+        Ok(())
+    }
+}
+
+/// Whether a slug can be used as a path, which it can't if it's empty or only dots (as browsers remove `.` and `..`
+/// from paths)
+fn is_valid_slug(slug: &str) -> bool {
+    !slug.trim_matches('.').is_empty()
+}
+
+/// Turns a server's address into a path for its status page, replacing anything other than letters, numbers,
+/// `-`, `_` and `.` with `-` (such as `play.example.com:25566` -> `play.example.com-25566`)
+fn slugify(server: &str) -> String {
+    server
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '-',
+        })
+        .collect::<String>()
+        .trim_matches('-')
+        .to_string()
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct Server {
     /// Initial server ip/domain passed
     pub(crate) server: String,
-    /// Ip to check minecraft status for
-    pub(crate) ip: IpAddr,
-    /// Port minecraft server is listening on
-    pub(crate) port: u16,
+    /// Ip or domain part of the initial server, which java servers are sent in the handshake
+    pub(crate) host: String,
+    /// Port passed with the initial server, or the default if none was given
+    host_port: u16,
+    /// Addresses to check minecraft status for, in the order they should be tried.
+    /// Empty if the server has never been resolved successfully
+    pub(crate) endpoints: Vec<SocketAddr>,
+    /// When to look up the server again, if a lookup is needed: straight away if it hasn't been looked up yet,
+    /// once the dns records used to find `endpoints` expire, or after a backoff if the last lookup failed
+    expires: Option<Instant>,
+    /// Number of lookups that have failed in a row
+    failures: u32,
+    /// Why the last lookup failed, if it did
+    pub(crate) dns_error: Option<String>,
+    /// How often to refresh data for this server
+    pub(crate) refresh_interval: Duration,
+    /// How the server is shown on the status pages
+    pub(crate) info: ServerInfo,
+    /// Edition the server is configured as
+    pub(crate) edition: Edition,
+    /// Edition that last responded, which is tried before any others
+    pub(crate) detected_edition: Option<Edition>,
 }
 
 impl Server {
-    pub fn parse(server_and_port: &str) -> Result<Self> {
-        // if string contains :, try and parse whatever follows it as a port
-        // use DEFAULT_PORT if invalid or no port provided
-        let (server, port) = match server_and_port.split_once(':') {
-            Some((server, port)) => match port.parse() {
-                Ok(port) => (server, port),
-                _ => {
-                    warn!("env var `SERVER` has invalid port `{port}`");
-                    (server, DEFAULT_PORT)
-                }
-            },
-            None => (server_and_port, DEFAULT_PORT),
+    /// Parses a server in the format `host[:port]`, where host is a domain, ipv4 address, or ipv6 address
+    /// (in brackets if a port is given, such as `[2001:db8::1]:25565`).
+    ///
+    /// The default port for the given edition is used if no port is given. Domains aren't looked up here, so a slow
+    /// dns server doesn't hold up starting - they're resolved by the task that checks the server instead
+    pub fn parse(server_and_port: &str, edition: Edition) -> Result<Self> {
+        let server_and_port = server_and_port.trim();
+        let (server, port) = split_host_port(server_and_port)?;
+        let port = port.unwrap_or(edition.default_port());
+
+        let mut parsed = Self {
+            server: server_and_port.to_string(),
+            host: server.to_string(),
+            host_port: port,
+            endpoints: Vec::new(),
+            expires: None,
+            failures: 0,
+            dns_error: None,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            info: ServerInfo::new(server_and_port),
+            edition,
+            detected_edition: None,
         };
 
         // if server is just an ip address, use directly
-        // otherwise perform dns lookup
-        let (ip, port) = match IpAddr::from_str(server) {
+        // otherwise it needs looking up before it's first checked
+        match IpAddr::from_str(server) {
             Ok(ip) => {
                 debug!("skipping dns look for {server}");
-                (ip, port)
+                parsed.endpoints = vec![SocketAddr::new(ip, port)];
+                debug!("adding endpoints {:?}", parsed.endpoints);
             }
-            _ => {
-                debug!("searching for server `{server}` with port `{port}`");
-                domain_lookup(server, port)?
+            _ => parsed.expires = Some(Instant::now()),
+        }
+
+        Ok(parsed)
+    }
+
+    /// Whether the dns records used to find this server have expired (or the last lookup failed and its backoff
+    /// has passed), so it should be looked up again
+    pub fn needs_resolving(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| expires <= Instant::now())
+    }
+
+    /// How long until the server should be looked up again, if the last lookup failed
+    pub fn retry_in(&self) -> Option<Duration> {
+        self.expires
+            .filter(|_| self.failures > 0)
+            .map(|expires| expires.saturating_duration_since(Instant::now()))
+    }
+
+    /// Looks up the endpoints for this server again, keeping the previous values if the lookup fails.
+    ///
+    /// Failed lookups are retried after an exponential backoff, which is reset once a lookup succeeds
+    pub async fn resolve(&mut self, resolver: &Resolver) -> Result<()> {
+        debug!("resolving server `{}`", self.server);
+
+        // bedrock clients never use SRV records, so a java SRV record on the same domain mustn't change the port
+        let resolver = match self.edition {
+            Edition::Bedrock => Cow::Owned(Resolver {
+                srv_lookup: false,
+                ..resolver.clone()
+            }),
+            _ => Cow::Borrowed(resolver),
+        };
+
+        let lookups = match resolver.lookup_all(&self.host, self.host_port).await {
+            Ok(lookups) => lookups,
+            Err(e) => {
+                self.expires = Some(Instant::now() + retry_delay(self.failures));
+                self.failures += 1;
+                self.dns_error = Some(e.to_string());
+                return Err(e.into());
             }
         };
+        let endpoints = endpoints(&self.server, &lookups);
 
-        debug!("adding ip `{ip}` with port `{port}`");
+        if endpoints != self.endpoints {
+            info!(
+                "server `{}` moved from {:?} to {endpoints:?}",
+                self.server, self.endpoints
+            );
+        }
 
-        Ok(Self {
-            server: server_and_port.to_string(),
-            ip,
-            port,
-        })
+        self.endpoints = endpoints;
+        self.expires = Some(expiry(&lookups));
+        self.failures = 0;
+        self.dns_error = None;
+
+        Ok(())
+    }
+
+    /// Editions to query, in the order they should be tried, with the edition that last responded first
+    pub fn query_editions(&self) -> Vec<Edition> {
+        // sorting is stable, so the other editions keep their order after the one that last responded
+        let mut editions = self.edition.candidates().to_vec();
+        if let Some(detected) = self.detected_edition {
+            editions.sort_by_key(|&edition| edition != detected);
+        }
+
+        editions
+    }
+
+    /// Remembers which edition responded to a check (if any did), so it's tried first next time.
+    ///
+    /// A missed check keeps the previous edition, so a blip doesn't mean waiting for the others to time out
+    pub fn set_responded_edition(&mut self, edition: Option<Edition>) {
+        if let Some(edition) = edition {
+            if Some(edition) != self.detected_edition {
+                info!("`{}` responded as {edition}", self.server);
+            }
+            self.detected_edition = Some(edition);
+        }
+    }
+}
+
+/// Splits a server into its host and port (if one is given), as described by
+/// [RFC 3986 section 3.2](https://datatracker.ietf.org/doc/html/rfc3986#section-3.2).
+///
+/// Ipv6 addresses must be in brackets to be followed by a port, but can be given without brackets otherwise
+fn split_host_port(server: &str) -> Result<(&str, Option<u16>)> {
+    let invalid = |reason: &str| anyhow!("invalid server `{server}`: {reason}");
+    let parse_port = |port: &str| match port.parse() {
+        Ok(0) | Err(_) => Err(invalid(&format!("invalid port `{port}`"))),
+        Ok(port) => Ok(Some(port)),
+    };
+
+    let (host, port) = if let Some(bracketed) = server.strip_prefix('[') {
+        let (host, rest) = bracketed
+            .split_once(']')
+            .ok_or_else(|| invalid("missing `]`"))?;
+        if Ipv6Addr::from_str(host).is_err() {
+            return Err(invalid("only ipv6 addresses can be in brackets"));
+        }
+
+        let port = match rest {
+            "" => None,
+            _ => parse_port(
+                rest.strip_prefix(':')
+                    .ok_or_else(|| invalid("expected `:` after `]`"))?,
+            )?,
+        };
+
+        (host, port)
+    } else if Ipv6Addr::from_str(server).is_ok() {
+        (server, None)
+    } else {
+        match server.split_once(':') {
+            Some((_, port)) if port.contains(':') => {
+                return Err(invalid("ipv6 addresses must be in brackets to have a port"))
+            }
+            Some((host, port)) => (host, parse_port(port)?),
+            None => (server, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err(invalid("missing host"));
     }
+
+    Ok((host, port))
+}
+
+/// Finds how long to wait before looking up a server again after the given number of earlier failures in a row
+fn retry_delay(failures: u32) -> Duration {
+    INITIAL_DNS_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(MAX_DNS_RETRY_DELAY)
+}
+
+/// Finds the addresses from a lookup, logging where each one came from
+fn endpoints(server: &str, lookups: &[Lookup]) -> Vec<SocketAddr> {
+    lookups
+        .iter()
+        .map(|lookup| {
+            debug!(
+                "resolved `{server}` via {} ({:?})",
+                lookup.describe_chain(),
+                lookup.origin
+            );
+
+            SocketAddr::new(lookup.ip, lookup.port)
+        })
+        .collect()
+}
+
+/// Finds when the first of the records used by a lookup expires
+fn expiry(lookups: &[Lookup]) -> Instant {
+    let ttl = lookups
+        .iter()
+        .map(|lookup| lookup.ttl)
+        .min()
+        .unwrap_or_default();
+
+    Instant::now() + ttl
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dns::{FakeServer, Zone};
+    use std::net::Ipv4Addr;
 
-    #[test]
-    fn test_config() {
-        let _ = dotenvy::dotenv();
-        let config = Config::from_env_vars();
+    /// Env var lookup which only has the given env vars set, so tests don't depend on (or change) the real ones
+    fn env<const N: usize>(vars: [(&str, &str); N]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .into();
 
-        let server = std::env::var("TEST_URL").unwrap();
-        let ip = std::env::var("TEST_IP").unwrap().parse().unwrap();
-        let port = std::env::var("TEST_PORT").unwrap().parse().unwrap();
+        move |name| vars.get(name).cloned()
+    }
 
-        assert!(config.is_ok());
+    #[tokio::test]
+    async fn test_config() {
+        let dns_server = FakeServer::start(
+            Zone::new()
+                .srv("_minecraft._tcp.config.test", 0, 5, 25570, "mc.config.test")
+                .a("mc.config.test", Ipv4Addr::new(10, 0, 2, 1)),
+        );
+        let endpoints = |config: &Config| {
+            config
+                .servers
+                .iter()
+                .map(|s| (s.server.clone(), s.endpoints.clone(), s.refresh_interval))
+                .collect::<Vec<_>>()
+        };
 
-        let config = config.unwrap();
+        // domains are looked up by the task checking each server, so do the same here
+        async fn resolve(config: &mut Config) {
+            for server in &mut config.servers {
+                if server.needs_resolving() {
+                    server.resolve(&config.resolver).await.unwrap();
+                }
+            }
+        }
+
+        // settings from the config file are used when env vars aren't set
+        let file_env = env([("DNS_FALLBACK_SERVERS", "none")]);
+
+        let file = ConfigFile::parse(&format!(
+            r#"
+            refresh_interval = "2m"
+            dns_servers = ["{}"]
+            dns_fallback_servers = ["10.0.0.53"]
+
+            [[servers]]
+            address = "config.test"
+            refresh_interval = "10s"
+
+            [[servers]]
+            address = "10.0.2.2:25571"
+            name = "Lobby"
+            slug = "lobby"
+            order = -1
+
+            [[servers]]
+            address = "10.0.2.4"
+            edition = "bedrock"
+            "#,
+            dns_server.address()
+        ))
+        .unwrap();
+
+        let mut config = Config::from_file(file.clone(), &file_env).unwrap();
+        assert_eq!(dns_server.queries(), 0);
+        assert!(config.servers[1].endpoints.is_empty());
+        assert!(config.servers[1].needs_resolving());
+
+        resolve(&mut config).await;
+        assert_eq!(config.refresh_interval, Duration::from_secs(120));
+        assert_eq!(
+            config.resolver.config.nameservers,
+            [dns_server.nameserver()]
+        );
+        assert!(config.resolver.fallback_servers.is_empty());
+        assert_eq!(
+            endpoints(&config),
+            [
+                (
+                    "10.0.2.2:25571".to_string(),
+                    vec!["10.0.2.2:25571".parse().unwrap()],
+                    Duration::from_secs(120)
+                ),
+                (
+                    "config.test".to_string(),
+                    vec!["10.0.2.1:25570".parse().unwrap()],
+                    Duration::from_secs(10)
+                ),
+                (
+                    "10.0.2.4".to_string(),
+                    vec!["10.0.2.4:19132".parse().unwrap()],
+                    Duration::from_secs(120)
+                )
+            ]
+        );
+        assert_eq!(config.servers[0].info.name, "Lobby");
+        assert_eq!(config.servers[0].info.slug, "lobby");
+        assert_eq!(config.servers[1].info.name, "config.test");
+
+        // env vars take priority, with servers from env vars replacing the ones in the file
+        let servers_env = env([
+            ("REFRESH_INTERVAL", "30s"),
+            ("SERVER", "config.test,10.0.2.2:25571, [fd00::2:2]:25572"),
+            ("DNS_FALLBACK_SERVERS", "none"),
+        ]);
+
+        let mut config = Config::from_file(file, servers_env).unwrap();
+        resolve(&mut config).await;
         assert_eq!(config.refresh_interval, Duration::from_secs(30));
-        assert!(config.servers.contains(&Server { server, ip, port }));
+        assert_eq!(
+            endpoints(&config),
+            [
+                (
+                    "config.test".to_string(),
+                    vec!["10.0.2.1:25570".parse().unwrap()],
+                    Duration::from_secs(30)
+                ),
+                (
+                    "10.0.2.2:25571".to_string(),
+                    vec!["10.0.2.2:25571".parse().unwrap()],
+                    Duration::from_secs(30)
+                ),
+                (
+                    "[fd00::2:2]:25572".to_string(),
+                    vec!["[fd00::2:2]:25572".parse().unwrap()],
+                    Duration::from_secs(30)
+                )
+            ]
+        );
+
+        assert!(Config::from_file(ConfigFile::default(), &file_env).is_err());
+
+        // each server needs its own slug
+        let file = ConfigFile::parse(
+            "[[servers]]\naddress = \"10.0.2.2\"\n[[servers]]\naddress = \"10.0.2.3\"\nslug = \"10.0.2.2\"",
+        )
+        .unwrap();
+        assert!(Config::from_file(file, &file_env).is_err());
     }
 
     #[test]
-    fn test_domain_lookup() {
-        let _ = dotenvy::dotenv();
+    fn test_invalid_settings() {
+        for (env_var, value) in [
+            ("REFRESH_INTERVAL", "soon"),
+            ("REFRESH_INTERVAL", "0s"),
+            ("IP_PREFERENCE", "ipv5"),
+            ("DNS_PORT", "dns"),
+            ("DNS_TIMEOUT", "0s"),
+        ] {
+            let env = env([("SERVER", "10.0.2.2"), (env_var, value)]);
+            assert!(
+                Config::from_file(ConfigFile::default(), env).is_err(),
+                "{env_var}={value} was accepted"
+            );
+        }
+
+        let file =
+            ConfigFile::parse("[[servers]]\naddress = \"10.0.2.2\"\nrefresh_interval = \"0s\"")
+                .unwrap();
+        assert!(Config::from_file(file, env([])).is_err());
+    }
+
+    #[test]
+    fn test_parse_static_hosts() {
+        let hosts = parse_static_hosts("mc-survival=172.18.0.5, mc-creative=[::1]:25566").unwrap();
 
-        let url = std::env::var("TEST_URL").unwrap();
+        assert_eq!(
+            hosts["mc-survival"].ip,
+            "172.18.0.5".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(hosts["mc-survival"].port, None);
+        assert_eq!(hosts["mc-creative"].port, Some(25566));
 
-        let ip = std::env::var("TEST_IP").unwrap().parse().unwrap();
-        let port = std::env::var("TEST_PORT").unwrap().parse().unwrap();
+        assert!(parse_static_hosts("mc-survival").is_err());
+        assert!(parse_static_hosts("mc-survival=mc-creative").is_err());
+    }
 
-        let result = domain_lookup(&url, DEFAULT_PORT);
+    #[test]
+    fn test_parse_dns_servers() {
+        let servers = parse_dns_servers(
+            "DNS_SERVERS",
+            "10.0.0.53, [fd00::53]:53, tls://dns.example.com@10.0.0.54",
+            5353,
+        )
+        .unwrap();
+
+        assert_eq!(
+            servers,
+            [
+                "10.0.0.53:5353".parse::<Nameserver>().unwrap(),
+                "[fd00::53]:53".parse().unwrap(),
+                "tls://dns.example.com@10.0.0.54:853".parse().unwrap()
+            ]
+        );
+        assert!(parse_dns_servers("DNS_SERVERS", "none", 53)
+            .unwrap()
+            .is_empty());
+        assert!(parse_dns_servers("DNS_SERVERS", "dns.example.com", 53).is_err());
+    }
+
+    #[test]
+    fn test_set_default_port() {
+        let mut servers = [
+            "10.0.0.53".parse::<Nameserver>().unwrap(),
+            "10.0.0.54:5353".parse().unwrap(),
+        ];
+        set_default_port(&mut servers, 5354);
+
+        assert_eq!(
+            servers,
+            [
+                "10.0.0.53:5354".parse().unwrap(),
+                "10.0.0.54:5353".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_server_info() {
+        assert_eq!(
+            ServerInfo::new("play.example.com:25566").slug,
+            "play.example.com-25566"
+        );
+        assert_eq!(
+            ServerInfo::new("[2001:db8::1]:25566").slug,
+            "2001-db8--1--25566"
+        );
+
+        // addresses without a usable slug (or which would replace another page) need one to be set
+        for address in ["::", "[::]", "..", ".", "metrics", "api"] {
+            let entry = ServerEntry::new(address);
+            assert!(
+                ServerInfo::new(address).apply(&entry).is_err(),
+                "{address} was accepted"
+            );
+
+            let entry = ServerEntry {
+                slug: Some("localhost".to_string()),
+                ..entry
+            };
+            assert!(ServerInfo::new(address).apply(&entry).is_ok());
+        }
+
+        let mut info = ServerInfo::new("play.example.com");
+        info.apply(&ServerEntry {
+            name: Some("Survival".to_string()),
+            slug: Some("survival".to_string()),
+            discord: Some("https://discord.gg/example".to_string()),
+            order: Some(2),
+            ..ServerEntry::new("play.example.com")
+        })
+        .unwrap();
+        assert_eq!(
+            info,
+            ServerInfo {
+                address: "play.example.com".to_string(),
+                name: "Survival".to_string(),
+                slug: "survival".to_string(),
+                description: None,
+                website: None,
+                discord: Some("https://discord.gg/example".to_string()),
+                order: 2,
+            }
+        );
+
+        for invalid in [
+            ServerEntry {
+                slug: Some("survival/world".to_string()),
+                ..Default::default()
+            },
+            ServerEntry {
+                slug: Some(String::new()),
+                ..Default::default()
+            },
+            ServerEntry {
+                slug: Some("..".to_string()),
+                ..Default::default()
+            },
+            ServerEntry {
+                slug: Some("metrics".to_string()),
+                ..Default::default()
+            },
+            ServerEntry {
+                website: Some("javascript:alert(1)".to_string()),
+                ..Default::default()
+            },
+        ] {
+            assert!(info.apply(&invalid).is_err(), "{invalid:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(
+            split_host_port("example.com").unwrap(),
+            ("example.com", None)
+        );
+        assert_eq!(
+            split_host_port("example.com:25566").unwrap(),
+            ("example.com", Some(25566))
+        );
+        assert_eq!(
+            split_host_port("10.0.0.1:25566").unwrap(),
+            ("10.0.0.1", Some(25566))
+        );
+        assert_eq!(
+            split_host_port("[2001:db8::1]:25566").unwrap(),
+            ("2001:db8::1", Some(25566))
+        );
+        assert_eq!(
+            split_host_port("[2001:db8::1]").unwrap(),
+            ("2001:db8::1", None)
+        );
+        assert_eq!(
+            split_host_port("2001:db8::1").unwrap(),
+            ("2001:db8::1", None)
+        );
+        assert_eq!(split_host_port("::1").unwrap(), ("::1", None));
+
+        for invalid in [
+            "",
+            ":25565",
+            "example.com:",
+            "example.com:port",
+            "example.com:0",
+            "example.com:65536",
+            "2001:db8::1:25565:1",
+            "[2001:db8::1",
+            "[2001:db8::1]25565",
+            "[2001:db8::1]:",
+            "[example.com]:25565",
+            "[]",
+        ] {
+            assert!(split_host_port(invalid).is_err(), "{invalid} was accepted");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dns_retry() {
+        let dns_server = FakeServer::start(Zone::new().servfail("broken.retry.test"));
+        let resolver = dns_server.resolver();
+
+        // failing to resolve a server doesn't stop it being added, so it can be retried later
+        let mut server = Server::parse("broken.retry.test", Edition::Auto).unwrap();
+        assert!(server.needs_resolving());
+        assert!(server.resolve(&resolver).await.is_err());
+        assert!(server.endpoints.is_empty());
+        assert!(server.dns_error.as_ref().unwrap().contains("SERVFAIL"));
+        assert!(!server.needs_resolving());
+        assert!(server.retry_in().unwrap() <= INITIAL_DNS_RETRY_DELAY);
+
+        server.expires = Some(Instant::now());
+        assert!(server.needs_resolving());
+        assert!(server.resolve(&resolver).await.is_err());
+        assert_eq!(server.failures, 2);
+        assert!(server.retry_in().unwrap() > INITIAL_DNS_RETRY_DELAY);
+
+        assert_eq!(retry_delay(0), Duration::from_secs(5));
+        assert_eq!(retry_delay(3), Duration::from_secs(40));
+        assert_eq!(retry_delay(100), MAX_DNS_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_bedrock_skips_srv() {
+        let dns_server = FakeServer::start(
+            Zone::new()
+                .srv("_minecraft._tcp.geyser.test", 0, 5, 25565, "geyser.test")
+                .a("geyser.test", Ipv4Addr::new(10, 0, 2, 5)),
+        );
+        let resolver = dns_server.resolver();
+
+        let mut java = Server::parse("geyser.test", Edition::Java).unwrap();
+        java.resolve(&resolver).await.unwrap();
+        assert_eq!(java.endpoints, ["10.0.2.5:25565".parse().unwrap()]);
+
+        let mut bedrock = Server::parse("geyser.test", Edition::Bedrock).unwrap();
+        bedrock.resolve(&resolver).await.unwrap();
+        assert_eq!(bedrock.endpoints, ["10.0.2.5:19132".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_query_editions() {
+        // editions other than auto only ever query that edition
+        let mut bedrock = Server::parse("10.0.2.6", Edition::Bedrock).unwrap();
+        assert_eq!(bedrock.query_editions(), [Edition::Bedrock]);
+        bedrock.set_responded_edition(Some(Edition::Bedrock));
+        assert_eq!(bedrock.query_editions(), [Edition::Bedrock]);
+
+        let mut auto = Server::parse("10.0.2.6", Edition::Auto).unwrap();
+        assert_eq!(
+            auto.query_editions(),
+            [Edition::Java, Edition::Bedrock, Edition::Legacy]
+        );
+
+        // the edition that responded is tried first, with the others still tried after it
+        auto.set_responded_edition(Some(Edition::Bedrock));
+        assert_eq!(
+            auto.query_editions(),
+            [Edition::Bedrock, Edition::Java, Edition::Legacy]
+        );
+
+        // and stays first after a missed check
+        auto.set_responded_edition(None);
+        assert_eq!(auto.detected_edition, Some(Edition::Bedrock));
+        assert_eq!(
+            auto.query_editions(),
+            [Edition::Bedrock, Edition::Java, Edition::Legacy]
+        );
+    }
+
+    #[test]
+    fn test_domain_lookup() {
+        let dns_server = FakeServer::start(
+            Zone::new()
+                .srv(
+                    "_minecraft._tcp.lookup.test",
+                    0,
+                    5,
+                    25572,
+                    "proxy.lookup.test",
+                )
+                .cname("proxy.lookup.test", "mc.lookup.test")
+                .a("mc.lookup.test", Ipv4Addr::new(10, 0, 2, 3)),
+        );
 
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), (ip, port));
+        let result = dns_server
+            .resolver()
+            .lookup_blocking("lookup.test", Edition::Java.default_port())
+            .unwrap();
+        assert_eq!(
+            (result.ip, result.port),
+            (IpAddr::from([10, 0, 2, 3]), 25572)
+        );
     }
 }
@@ -0,0 +1,152 @@
+use crate::status::Edition;
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Config file read when `CONFIG_FILE` isn't set
+const DEFAULT_CONFIG_FILE: &str = "minecraft_status.toml";
+
+/// Settings read from a TOML config file, which are used as defaults for the matching env vars
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    /// How often to refresh data, for servers that don't set their own
+    pub(crate) refresh_interval: Option<String>,
+    /// Which address records to use when resolving servers
+    pub(crate) ip_preference: Option<String>,
+    /// Fixed addresses for server names, in the format `ip[:port]`
+    pub(crate) static_hosts: Option<HashMap<String, String>>,
+    /// Dns servers to use instead of the ones from the OS
+    pub(crate) dns_servers: Option<Vec<String>>,
+    /// Dns servers to try if the main ones can't resolve a server. Empty disables fallback
+    pub(crate) dns_fallback_servers: Option<Vec<String>>,
+    /// Port used for plain dns servers that don't have one given
+    pub(crate) dns_port: Option<u16>,
+    /// How long to wait for a dns server to respond before retrying
+    pub(crate) dns_timeout: Option<String>,
+    /// Servers to check
+    #[serde(default)]
+    pub(crate) servers: Vec<ServerEntry>,
+}
+
+/// A single server in the config file, along with any settings overridden for it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ServerEntry {
+    /// Ip or domain of the server, with an optional port
+    pub(crate) address: String,
+    /// How often to refresh this server, instead of the global refresh interval
+    pub(crate) refresh_interval: Option<String>,
+    /// Name shown for the server, instead of its address
+    pub(crate) name: Option<String>,
+    /// Path the server's status page is served on, instead of one based on its address
+    pub(crate) slug: Option<String>,
+    /// Description shown under the server's name
+    pub(crate) description: Option<String>,
+    /// Link to the server's website
+    pub(crate) website: Option<String>,
+    /// Link to the server's discord
+    pub(crate) discord: Option<String>,
+    /// Position of the server on the main page, with lower values shown first.
+    /// Servers with the same order are shown in the order they're listed
+    pub(crate) order: Option<i32>,
+    /// Edition of minecraft the server runs, which also decides its default port
+    pub(crate) edition: Option<Edition>,
+}
+
+impl ServerEntry {
+    /// Creates an entry for a server without any overrides, as given by the `SERVER` env var
+    pub(crate) fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Reads the config file at the path given by the `CONFIG_FILE` env var, or `minecraft_status.toml`.
+    ///
+    /// It's only an error for the file to be missing if `CONFIG_FILE` is set
+    pub(crate) fn load() -> Result<Self> {
+        let (path, required) = match std::env::var("CONFIG_FILE") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_FILE.to_string(), false),
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                info!("reading config file `{path}`");
+                Self::parse(&contents).map_err(|e| anyhow!("invalid config file `{path}`: {e}"))
+            }
+            Err(e) if required || e.kind() != std::io::ErrorKind::NotFound => {
+                Err(anyhow!("failed to read config file `{path}`: {e}"))
+            }
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Parses the contents of a config file
+    pub(crate) fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config_file() {
+        let file = ConfigFile::parse(
+            r#"
+            refresh_interval = "2m"
+            dns_servers = ["10.0.0.53", "tls://dns.example.com@10.0.0.54"]
+            dns_fallback_servers = []
+
+            [static_hosts]
+            mc-survival = "172.18.0.5:25565"
+
+            [[servers]]
+            address = "survival.example.com"
+
+            [[servers]]
+            address = "[2001:db8::1]:25566"
+            refresh_interval = "10s"
+            name = "Creative"
+            slug = "creative"
+            order = -1
+            edition = "bedrock"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(file.refresh_interval.as_deref(), Some("2m"));
+        assert_eq!(file.dns_fallback_servers, Some(Vec::new()));
+        assert_eq!(
+            file.static_hosts.unwrap()["mc-survival"],
+            "172.18.0.5:25565"
+        );
+        assert_eq!(
+            file.servers,
+            [
+                ServerEntry::new("survival.example.com"),
+                ServerEntry {
+                    address: "[2001:db8::1]:25566".to_string(),
+                    refresh_interval: Some("10s".to_string()),
+                    name: Some("Creative".to_string()),
+                    slug: Some("creative".to_string()),
+                    order: Some(-1),
+                    edition: Some(Edition::Bedrock),
+                    ..Default::default()
+                }
+            ]
+        );
+
+        // typos shouldn't be silently ignored
+        assert!(ConfigFile::parse("refresh_intervall = \"2m\"").is_err());
+        assert!(ConfigFile::parse("[[servers]]\nrefresh_interval = \"2m\"").is_err());
+        assert!(ConfigFile::parse("[[servers]]\naddress = \"a\"\nedition = \"pocket\"").is_err());
+    }
+}
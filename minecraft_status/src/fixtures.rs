@@ -0,0 +1,79 @@
+use crate::{
+    config::ServerInfo,
+    status::{Response, ServerStatus, StatusEntry},
+    Status,
+};
+use gamedig::protocols::minecraft::{BedrockResponse, GameMode, JavaResponse, Server};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+/// Time every fixture was last checked at, in seconds since the unix epoch
+pub(crate) const LAST_CHECKED: u64 = 1_700_000_000;
+
+/// Display settings for a server without any configured, with an address based on its slug
+pub(crate) fn server_info(slug: &str) -> ServerInfo {
+    ServerInfo {
+        address: format!("{slug}.example.com"),
+        name: slug.to_string(),
+        slug: slug.to_string(),
+        description: None,
+        website: None,
+        discord: None,
+        order: 0,
+    }
+}
+
+/// Response from a java server with 2 of 20 players online
+pub(crate) fn java_response() -> Response {
+    Response::Java(JavaResponse {
+        game_version: "1.20.2".to_string(),
+        protocol_version: 764,
+        players_maximum: 20,
+        players_online: 2,
+        players: None,
+        description: "A minecraft server".to_string(),
+        favicon: None,
+        previews_chat: None,
+        enforces_secure_chat: None,
+        server_type: Server::Java,
+    })
+}
+
+/// Response from a bedrock server with 3 of 10 players online, including the fields only bedrock servers have
+pub(crate) fn bedrock_response() -> Response {
+    Response::Bedrock(BedrockResponse {
+        edition: "MCPE".to_string(),
+        name: "Bedrock server".to_string(),
+        version_name: "1.20.40".to_string(),
+        protocol_version: "622".to_string(),
+        players_maximum: 10,
+        players_online: 3,
+        id: Some("12345678901234567890".to_string()),
+        map: Some("Bedrock level".to_string()),
+        game_mode: Some(GameMode::Creative),
+        server_type: Server::Bedrock,
+    })
+}
+
+/// Status of a server that was just checked, which responded with a latency of 35ms if it's online
+pub(crate) fn status_entry(status: ServerStatus) -> StatusEntry {
+    let online = matches!(status, ServerStatus::Online(_));
+
+    StatusEntry {
+        status,
+        last_checked: Some(LAST_CHECKED),
+        last_error: None,
+        last_online: online.then_some(LAST_CHECKED),
+        latency_ms: online.then_some(35),
+        query_failures: BTreeMap::new(),
+    }
+}
+
+/// Shared status holding the given entries, keyed by slug
+pub(crate) fn shared_status<const N: usize>(entries: [(&str, StatusEntry); N]) -> Status {
+    Arc::new(RwLock::new(HashMap::from(
+        entries.map(|(slug, entry)| (slug.to_string(), entry)),
+    )))
+}
@@ -1,25 +1,36 @@
 //#![deny(unsafe_code)]
 
+mod api;
 mod config;
+mod config_file;
+#[cfg(test)]
+mod fixtures;
+mod metrics;
+mod ping;
+mod status;
 
-use crate::config::Server;
+use crate::{
+    config::{Server, ServerInfo},
+    ping::ping_java,
+    status::{Edition, Response, ServerStatus, StatusEntry},
+};
 use anyhow::Result;
-use axum::{response::Html, routing::get, Router};
+use axum::{extract::Path, response::Html, routing::get, Router};
 use config::Config;
-use gamedig::{
-    games::minecraft,
-    protocols::minecraft::{JavaResponse, RequestSettings},
-};
+use gamedig::{games::minecraft, protocols::minecraft::RequestSettings};
 use log::{debug, info, warn, LevelFilter};
-use minijinja::render;
+use minijinja::{context, Environment, Value};
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Arc, OnceLock, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 const DEFAULT_PORT: u16 = 3000;
 
-type Status = Arc<RwLock<HashMap<String, Option<JavaResponse>>>>;
+/// Status of each server, keyed by slug
+type Status = Arc<RwLock<HashMap<String, StatusEntry>>>;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -31,7 +42,7 @@ async fn main() -> Result<()> {
         .init()
         .unwrap();
 
-    let config = Config::from_env_vars()?;
+    let config = Config::load()?;
     info!("using config {config:?}");
 
     // create shared server status and fill with servers from config
@@ -39,32 +50,100 @@ async fn main() -> Result<()> {
         config
             .servers
             .iter()
-            .map(|server| (server.server.clone(), None))
+            .map(|server| {
+                let entry = StatusEntry {
+                    status: initial_status(server),
+                    last_checked: None,
+                    last_error: server.dns_error.clone(),
+                    last_online: None,
+                    latency_ms: None,
+                    query_failures: BTreeMap::new(),
+                };
+
+                (server.info.slug.clone(), entry)
+            })
             .collect(),
     ));
 
     // set up background process to refresh each server status
     for server in &config.servers {
-        let server = server.clone();
+        let mut server = server.clone();
         let status_clone = status.clone();
+        let resolver = config.resolver.clone();
+
+        tokio::spawn(async move {
+            loop {
+                // look up the server again if its dns records have expired, so moved servers are followed
+                if server.needs_resolving() {
+                    if let Err(e) = server.resolve(&resolver).await {
+                        warn!(
+                            "failed to resolve `{}`, retrying in {:?} and using any previous addresses: {e}",
+                            server.server,
+                            server.retry_in().unwrap_or_default()
+                        );
+                    }
+                }
+
+                // querying the server is blocking, so keep it off the async runtime
+                let (status, query_server) = (status_clone.clone(), server.clone());
+                let edition =
+                    tokio::task::spawn_blocking(move || update_status(&status, &query_server))
+                        .await
+                        .unwrap_or_default();
 
-        tokio::task::spawn_blocking(move || loop {
-            update_status(&status_clone, &server);
-            std::thread::sleep(config.refresh_interval);
+                server.set_responded_edition(edition);
+
+                // wake up early to retry a failed lookup, so a server with broken dns comes back as soon as it's fixed
+                let sleep = server
+                    .retry_in()
+                    .map_or(server.refresh_interval, |retry_in| {
+                        retry_in.min(server.refresh_interval)
+                    });
+                tokio::time::sleep(sleep).await;
+            }
         });
     }
 
-    // create router
-    let router_status = status.clone();
-    let mut router = Router::new().route("/", get(move || serve_all_status(router_status)));
+    // create router, with servers in the order they're shown
+    let servers: Arc<Vec<ServerInfo>> = Arc::new(
+        config
+            .servers
+            .iter()
+            .map(|server| server.info.clone())
+            .collect(),
+    );
+    let (router_servers, router_status) = (servers.clone(), status.clone());
+    let mut router = Router::new().route(
+        "/",
+        get(move || serve_all_status(router_servers, router_status)),
+    );
+
+    // json api, which finds servers by slug itself so unknown ones get a json error
+    let (api_servers, api_status) = (servers.clone(), status.clone());
+    router = router.route(
+        "/api/v1/servers",
+        get(move || api::serve_all_servers(api_servers, api_status)),
+    );
+    let (api_servers, api_status) = (servers.clone(), status.clone());
+    router = router.route(
+        "/api/v1/servers/:id",
+        get(move |id: Path<String>| api::serve_single_server(id, api_servers, api_status)),
+    );
+
+    // prometheus metrics
+    let (metrics_servers, metrics_status) = (servers.clone(), status.clone());
+    router = router.route(
+        "/metrics",
+        get(move || metrics::serve_metrics(metrics_servers, metrics_status)),
+    );
 
     // then add routes for each server
-    for server in config.servers.clone() {
+    for server in servers.iter().cloned() {
         let status = status.clone();
 
         router = router.route(
-            &format!("/{}", server.server),
-            get(move || serve_single_status(server.clone().server, status)),
+            &format!("/{}", server.slug),
+            get(move || serve_single_status(server.clone(), status)),
         )
     }
 
@@ -95,48 +174,177 @@ fn get_port() -> u16 {
     }
 }
 
-/// Updates a status with result from given server
-fn update_status(status: &Status, server: &Server) {
-    let java_request_settings = RequestSettings {
-        hostname: server.server.clone(),
-        protocol_version: -1, // query for any minecraft java versions
-    };
+/// Finds the status to show for a server before it's first checked
+fn initial_status(server: &Server) -> ServerStatus {
+    match &server.dns_error {
+        Some(reason) if server.endpoints.is_empty() => ServerStatus::DnsFailure {
+            reason: reason.clone(),
+        },
+        _ => ServerStatus::Offline,
+    }
+}
 
-    // get new status, trying java and then bedrock
-    let new_status = if let Ok(response) =
-        minecraft::query_java(&server.ip, Some(server.port), Some(java_request_settings))
-    {
-        Some(response)
-    } else if let Ok(response) = minecraft::query_bedrock(&server.ip, Some(server.port)) {
-        Some(JavaResponse::from_bedrock_response(response))
-    } else {
-        None
+/// Updates a status with result from given server, trying each of its endpoints in order until one responds.
+///
+/// Servers that have never resolved are marked as a dns failure, while servers which fail to resolve again keep
+/// using their previous addresses. Returns the edition that responded, if any did
+fn update_status(status: &Status, server: &Server) -> Option<Edition> {
+    // carry on from the previous check, as this is the only task that updates this server
+    let (mut query_failures, last_online) = status
+        .read()
+        .unwrap()
+        .get(&server.info.slug)
+        .map(|entry| (entry.query_failures.clone(), entry.last_online))
+        .unwrap_or_default();
+
+    // a server without any addresses can only have failed to resolve
+    let mut last_error = server.dns_error.clone();
+    let response = server.endpoints.iter().find_map(|endpoint| {
+        match query_endpoint(server, endpoint, &mut query_failures) {
+            Ok(response) => Some(response),
+            Err(e) => {
+                debug!("`{}` didn't respond at {endpoint}", server.server);
+                last_error = Some(e);
+                None
+            }
+        }
+    });
+    let edition = response.as_ref().map(|(response, _)| response.edition());
+    let last_checked = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|since| since.as_secs());
+    let entry = StatusEntry {
+        last_checked,
+        last_error: response.is_none().then_some(last_error).flatten(),
+        last_online: if response.is_some() {
+            last_checked
+        } else {
+            last_online
+        },
+        latency_ms: response
+            .as_ref()
+            .map(|(_, latency)| latency.as_millis() as u64),
+        query_failures,
+        status: response.map_or_else(
+            || initial_status(server),
+            |(response, _)| ServerStatus::Online(response),
+        ),
     };
 
     // then log and write to shared status
-    debug!("status for `{}`:\n\t{new_status:?}", server.server);
+    debug!("status for `{}`:\n\t{entry:?}", server.server);
 
     status
         .write()
         .unwrap()
-        .insert(server.server.clone(), new_status);
+        .insert(server.info.slug.clone(), entry);
+
+    edition
+}
+
+/// Queries a single endpoint of a server using each edition it could be in turn, starting with the one that
+/// last responded if there is one. Each failed query is counted against its edition, and if none respond the error
+/// from the last one tried is returned.
+///
+/// The response is returned along with its latency, which for java servers is measured with a separate ping so
+/// it isn't skewed by the time taken to connect and build the status
+fn query_endpoint(
+    server: &Server,
+    endpoint: &SocketAddr,
+    query_failures: &mut BTreeMap<Edition, u64>,
+) -> Result<(Response, Duration), String> {
+    let (ip, port) = (endpoint.ip(), Some(endpoint.port()));
+    let mut last_error = String::new();
+    server
+        .query_editions()
+        .into_iter()
+        .find_map(|edition| {
+            let start = Instant::now();
+            let response = match edition {
+                Edition::Java => {
+                    // proxies route on the address in the handshake, which doesn't include the port
+                    let java_request_settings = RequestSettings {
+                        hostname: server.host.clone(),
+                        protocol_version: -1, // query for any minecraft java versions
+                    };
+
+                    minecraft::query_java(&ip, port, Some(java_request_settings))
+                        .map(Response::Java)
+                }
+                Edition::Bedrock => minecraft::query_bedrock(&ip, port).map(Response::Bedrock),
+                Edition::Legacy => minecraft::query_legacy(&ip, port).map(Response::Legacy),
+                Edition::Auto => unreachable!("auto is never a candidate edition"),
+            };
+            let query_time = start.elapsed();
+
+            match response {
+                Ok(Response::Java(response)) => {
+                    let latency = ping_java(endpoint, &server.host).unwrap_or_else(|e| {
+                        debug!(
+                            "failed to ping `{}` at {endpoint}, using query time instead: {e}",
+                            server.server
+                        );
+                        query_time
+                    });
+
+                    Some((Response::Java(response), latency))
+                }
+                Ok(response) => Some((response, query_time)),
+                Err(e) => {
+                    debug!(
+                        "`{}` didn't respond as {edition} at {endpoint}: {e}",
+                        server.server
+                    );
+                    last_error = format!("didn't respond as {edition} at {endpoint}: {e}");
+                    *query_failures.entry(edition).or_default() += 1;
+                    None
+                }
+            }
+        })
+        .ok_or(last_error)
 }
 
-/// Serves the status of all servers
-async fn serve_all_status(status: Status) -> Html<String> {
-    const SERVE_ALL_STATUS: &'static str = include_str!("../templates/all.html");
+/// Renders one of the status page templates.
+///
+/// Templates are html escaped, as names, MOTDs and dns errors come from the servers being checked
+fn render_page(name: &str, context: Value) -> String {
+    static TEMPLATES: OnceLock<Environment> = OnceLock::new();
+
+    let templates = TEMPLATES.get_or_init(|| {
+        let mut templates = Environment::new();
+        templates
+            .add_template("all.html", include_str!("../templates/all.html"))
+            .unwrap();
+        templates
+            .add_template("single.html", include_str!("../templates/single.html"))
+            .unwrap();
+        templates
+    });
 
+    templates
+        .get_template(name)
+        .and_then(|template| template.render(context))
+        .unwrap()
+}
+
+/// Serves the status of all servers, in the order they're configured to be shown
+async fn serve_all_status(servers: Arc<Vec<ServerInfo>>, status: Status) -> Html<String> {
     let read = (*status.read().unwrap()).clone();
 
-    Html(render!(SERVE_ALL_STATUS, statuses => read))
+    Html(render_page(
+        "all.html",
+        context! { servers => servers.as_slice(), statuses => read },
+    ))
 }
 
 /// Serves the status of a single server
-async fn serve_single_status(server: String, status: Status) -> Html<String> {
-    const SERVE_SINGLE_STATUS: &'static str = include_str!("../templates/single.html");
-
+async fn serve_single_status(server: ServerInfo, status: Status) -> Html<String> {
     let read = status.read().unwrap();
-    let response = read.get(&server).unwrap();
+    let entry = read.get(&server.slug).unwrap();
 
-    Html(render!(SERVE_SINGLE_STATUS, server => server, status => response))
+    Html(render_page(
+        "single.html",
+        context! { server => server, status => entry },
+    ))
 }
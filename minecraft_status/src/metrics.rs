@@ -0,0 +1,200 @@
+use crate::{config::ServerInfo, status::ServerStatus, Status};
+use axum::http::{header::CONTENT_TYPE, HeaderName};
+use std::{
+    fmt::{Display, Write},
+    sync::Arc,
+};
+
+/// Content type of the prometheus text format
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Serves the status of all servers in the prometheus text format
+pub(crate) async fn serve_metrics(
+    servers: Arc<Vec<ServerInfo>>,
+    status: Status,
+) -> ([(HeaderName, &'static str); 1], String) {
+    (
+        [(CONTENT_TYPE, METRICS_CONTENT_TYPE)],
+        render_metrics(&servers, &status),
+    )
+}
+
+/// Writes the metrics for each server, in the order they're configured to be shown
+fn render_metrics(servers: &[ServerInfo], status: &Status) -> String {
+    let read = status.read().unwrap();
+    let entries: Vec<_> = servers
+        .iter()
+        .filter_map(|server| Some((server, read.get(&server.slug)?)))
+        .collect();
+
+    let mut metrics = Metrics::default();
+
+    metrics.family(
+        "minecraft_status_up",
+        "gauge",
+        "Whether the server responded the last time it was checked",
+    );
+    for (server, entry) in &entries {
+        let up = matches!(entry.status, ServerStatus::Online(_));
+        metrics.sample("minecraft_status_up", server, &[], u64::from(up));
+    }
+
+    metrics.family(
+        "minecraft_status_players_online",
+        "gauge",
+        "Number of players online",
+    );
+    for (server, entry) in &entries {
+        if let ServerStatus::Online(response) = &entry.status {
+            let players = response.players_online();
+            metrics.sample("minecraft_status_players_online", server, &[], players);
+        }
+    }
+
+    metrics.family(
+        "minecraft_status_players_max",
+        "gauge",
+        "Number of players the server can hold",
+    );
+    for (server, entry) in &entries {
+        if let ServerStatus::Online(response) = &entry.status {
+            let players = response.players_maximum();
+            metrics.sample("minecraft_status_players_max", server, &[], players);
+        }
+    }
+
+    metrics.family(
+        "minecraft_status_latency_seconds",
+        "gauge",
+        "Round trip time of the last check, only for servers that responded",
+    );
+    for (server, entry) in &entries {
+        if let Some(latency_ms) = entry.latency_ms {
+            let latency = latency_ms as f64 / 1000.0;
+            metrics.sample("minecraft_status_latency_seconds", server, &[], latency);
+        }
+    }
+
+    metrics.family(
+        "minecraft_status_last_success_timestamp_seconds",
+        "gauge",
+        "When the server last responded, in seconds since the unix epoch",
+    );
+    for (server, entry) in &entries {
+        if let Some(last_online) = entry.last_online {
+            let name = "minecraft_status_last_success_timestamp_seconds";
+            metrics.sample(name, server, &[], last_online);
+        }
+    }
+
+    metrics.family(
+        "minecraft_status_query_failures_total",
+        "counter",
+        "Number of queries the server didn't respond to, by the edition it was queried as",
+    );
+    for (server, entry) in &entries {
+        for (edition, failures) in &entry.query_failures {
+            let edition = edition.to_string();
+            let name = "minecraft_status_query_failures_total";
+            metrics.sample(name, server, &[("edition", &edition)], *failures);
+        }
+    }
+
+    metrics.0
+}
+
+/// Metrics being written in the prometheus text format
+#[derive(Default)]
+struct Metrics(String);
+
+impl Metrics {
+    /// Starts a new metric family, which all following samples with the same name belong to
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {name} {help}");
+        let _ = writeln!(self.0, "# TYPE {name} {kind}");
+    }
+
+    /// Writes a single sample for a server, labelled with its slug and address
+    fn sample(
+        &mut self,
+        name: &str,
+        server: &ServerInfo,
+        labels: &[(&str, &str)],
+        value: impl Display,
+    ) {
+        let mut all_labels = format!(
+            "server=\"{}\",address=\"{}\"",
+            escape_label(&server.slug),
+            escape_label(&server.address)
+        );
+        for (label, value) in labels {
+            let _ = write!(all_labels, ",{label}=\"{}\"", escape_label(value));
+        }
+
+        let _ = writeln!(self.0, "{name}{{{all_labels}}} {value}");
+    }
+}
+
+/// Escapes a label value, as backslashes, quotes and newlines have special meaning
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{bedrock_response, server_info, shared_status, status_entry},
+        status::{Edition, StatusEntry},
+    };
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_render_metrics() {
+        let servers = ["bedrock", "offline"].map(server_info);
+        let status = shared_status([
+            (
+                "bedrock",
+                StatusEntry {
+                    query_failures: BTreeMap::from([(Edition::Java, 1)]),
+                    ..status_entry(ServerStatus::Online(bedrock_response()))
+                },
+            ),
+            (
+                "offline",
+                StatusEntry {
+                    last_error: Some("timed out".to_string()),
+                    query_failures: BTreeMap::from([(Edition::Java, 2), (Edition::Legacy, 2)]),
+                    ..status_entry(ServerStatus::Offline)
+                },
+            ),
+        ]);
+
+        let metrics = render_metrics(&servers, &status);
+        for line in [
+            "# TYPE minecraft_status_up gauge",
+            r#"minecraft_status_up{server="bedrock",address="bedrock.example.com"} 1"#,
+            r#"minecraft_status_up{server="offline",address="offline.example.com"} 0"#,
+            r#"minecraft_status_players_online{server="bedrock",address="bedrock.example.com"} 3"#,
+            r#"minecraft_status_players_max{server="bedrock",address="bedrock.example.com"} 10"#,
+            r#"minecraft_status_latency_seconds{server="bedrock",address="bedrock.example.com"} 0.035"#,
+            r#"minecraft_status_last_success_timestamp_seconds{server="bedrock",address="bedrock.example.com"} 1700000000"#,
+            "# TYPE minecraft_status_query_failures_total counter",
+            r#"minecraft_status_query_failures_total{server="bedrock",address="bedrock.example.com",edition="java"} 1"#,
+            r#"minecraft_status_query_failures_total{server="offline",address="offline.example.com",edition="legacy"} 2"#,
+        ] {
+            assert!(
+                metrics.lines().any(|l| l == line),
+                "missing `{line}` in:\n{metrics}"
+            );
+        }
+
+        // offline servers have no player counts
+        assert!(!metrics.contains(r#"minecraft_status_players_online{server="offline""#));
+
+        assert_eq!(escape_label("a\"b\\c\nd"), r#"a\"b\\c\nd"#);
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How long to wait for a server to accept a connection or respond, matching the timeout gamedig uses
+const PING_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Largest packet accepted from a server, which is the largest length the protocol allows
+const MAX_PACKET_SIZE: usize = (1 << 21) - 1;
+
+/// Measures the round trip time to a java server with the ping and pong packets of the status protocol.
+///
+/// Unlike timing the whole status query, this doesn't include connecting or the time the server takes to
+/// build its status, so it matches the ping shown in the multiplayer menu
+pub(crate) fn ping_java(address: &SocketAddr, hostname: &str) -> io::Result<Duration> {
+    let mut stream = TcpStream::connect_timeout(address, PING_TIMEOUT)?;
+    stream.set_read_timeout(Some(PING_TIMEOUT))?;
+    stream.set_write_timeout(Some(PING_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+
+    // switch to the status state, then request the status as some servers won't answer a ping before it
+    let mut handshake = vec![0x00];
+    write_varint(&mut handshake, -1); // any protocol version
+    write_varint(&mut handshake, hostname.len() as i32);
+    handshake.extend_from_slice(hostname.as_bytes());
+    handshake.extend_from_slice(&address.port().to_be_bytes());
+    write_varint(&mut handshake, 1); // next state is status
+    send_packet(&mut stream, &handshake)?;
+    send_packet(&mut stream, &[0x00])?;
+    read_packet(&mut stream)?;
+
+    // the payload can be anything, but the server has to send the same one back
+    let payload = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64);
+    let mut ping = vec![0x01];
+    ping.extend_from_slice(&payload.to_be_bytes());
+
+    let start = Instant::now();
+    send_packet(&mut stream, &ping)?;
+    let pong = read_packet(&mut stream)?;
+    let latency = start.elapsed();
+
+    if pong != ping {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "pong didn't match ping",
+        ));
+    }
+
+    Ok(latency)
+}
+
+/// Sends a packet prefixed with its length
+fn send_packet(stream: &mut impl Write, packet: &[u8]) -> io::Result<()> {
+    let mut data = Vec::with_capacity(packet.len() + 5);
+    write_varint(&mut data, packet.len() as i32);
+    data.extend_from_slice(packet);
+
+    stream.write_all(&data)
+}
+
+/// Reads a packet prefixed with its length, returning the packet id followed by its data
+fn read_packet(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_varint(stream)? as usize;
+    if len > MAX_PACKET_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "packet too large",
+        ));
+    }
+
+    let mut packet = vec![0; len];
+    stream.read_exact(&mut packet)?;
+
+    Ok(packet)
+}
+
+/// Writes a variable length integer, using 7 bits per byte with the top bit set on all but the last byte
+fn write_varint(data: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value < 0x80 {
+            data.push(value as u8);
+            return;
+        }
+
+        data.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+}
+
+/// Reads a variable length integer, which is at most 5 bytes
+fn read_varint(stream: &mut impl Read) -> io::Result<i32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+
+        value |= u32::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "varint too long",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_varint() {
+        for (value, bytes) in [
+            (0, vec![0x00]),
+            (1, vec![0x01]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x01]),
+            (25565, vec![0xdd, 0xc7, 0x01]),
+            (-1, vec![0xff, 0xff, 0xff, 0xff, 0x0f]),
+        ] {
+            let mut data = Vec::new();
+            write_varint(&mut data, value);
+            assert_eq!(data, bytes);
+            assert_eq!(read_varint(&mut bytes.as_slice()).unwrap(), value);
+        }
+
+        assert!(read_varint(&mut [0xff; 6].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_ping_java() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // answer a single status request and ping like a java server
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let handshake = read_packet(&mut stream).unwrap();
+            assert_eq!(handshake[0], 0x00);
+            assert_eq!(*handshake.last().unwrap(), 0x01);
+            assert_eq!(read_packet(&mut stream).unwrap(), [0x00]);
+
+            let mut status = vec![0x00];
+            let json = br#"{"version":{"name":"1.20.2","protocol":764}}"#;
+            write_varint(&mut status, json.len() as i32);
+            status.extend_from_slice(json);
+            send_packet(&mut stream, &status).unwrap();
+
+            let ping = read_packet(&mut stream).unwrap();
+            assert_eq!(ping[0], 0x01);
+            send_packet(&mut stream, &ping).unwrap();
+        });
+
+        assert!(ping_java(&address, "localhost").unwrap() < PING_TIMEOUT);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+use gamedig::protocols::minecraft::{BedrockResponse, JavaResponse};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+/// Which edition of minecraft a server runs, and so which protocol is used to query it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Edition {
+    /// Java edition, from 1.7 onwards
+    Java,
+    /// Bedrock edition
+    Bedrock,
+    /// Java edition before 1.7 (beta 1.8 to 1.6)
+    Legacy,
+    /// Try each edition in turn, remembering the one that responds
+    #[default]
+    Auto,
+}
+
+impl Edition {
+    /// Port used by servers of this edition when none is given
+    pub(crate) fn default_port(self) -> u16 {
+        match self {
+            Edition::Bedrock => 19132,
+            _ => 25565,
+        }
+    }
+
+    /// Editions to query, in the order they should be tried
+    pub(crate) fn candidates(self) -> &'static [Edition] {
+        match self {
+            Edition::Java => &[Edition::Java],
+            Edition::Bedrock => &[Edition::Bedrock],
+            Edition::Legacy => &[Edition::Legacy],
+            Edition::Auto => &[Edition::Java, Edition::Bedrock, Edition::Legacy],
+        }
+    }
+}
+
+impl fmt::Display for Edition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Edition::Java => write!(f, "java"),
+            Edition::Bedrock => write!(f, "bedrock"),
+            Edition::Legacy => write!(f, "legacy"),
+            Edition::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// Response from a server, tagged with the edition it responded as so no fields are lost
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "edition", content = "response", rename_all = "snake_case")]
+pub(crate) enum Response {
+    /// Response from a java server
+    Java(JavaResponse),
+    /// Response from a bedrock server, including its game mode, map and server id
+    Bedrock(BedrockResponse),
+    /// Response from a java server before 1.7, which only has the player counts, version and MOTD
+    Legacy(JavaResponse),
+}
+
+impl Response {
+    /// Edition the server responded as
+    pub(crate) fn edition(&self) -> Edition {
+        match self {
+            Response::Java(_) => Edition::Java,
+            Response::Bedrock(_) => Edition::Bedrock,
+            Response::Legacy(_) => Edition::Legacy,
+        }
+    }
+
+    /// Number of players online
+    pub(crate) fn players_online(&self) -> u32 {
+        match self {
+            Response::Java(response) | Response::Legacy(response) => response.players_online,
+            Response::Bedrock(response) => response.players_online,
+        }
+    }
+
+    /// Number of players the server can hold
+    pub(crate) fn players_maximum(&self) -> u32 {
+        match self {
+            Response::Java(response) | Response::Legacy(response) => response.players_maximum,
+            Response::Bedrock(response) => response.players_maximum,
+        }
+    }
+
+    /// Version of minecraft the server is running
+    pub(crate) fn version(&self) -> &str {
+        match self {
+            Response::Java(response) | Response::Legacy(response) => &response.game_version,
+            Response::Bedrock(response) => &response.version_name,
+        }
+    }
+
+    /// Message of the day, which bedrock servers call their name
+    pub(crate) fn motd(&self) -> &str {
+        match self {
+            Response::Java(response) | Response::Legacy(response) => &response.description,
+            Response::Bedrock(response) => &response.name,
+        }
+    }
+}
+
+/// Last known state of a server, as shown on the status pages
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub(crate) enum ServerStatus {
+    /// The server responded, with the status it gave
+    Online(Response),
+    /// None of the server's addresses responded, or it hasn't been checked yet
+    Offline,
+    /// The server's domain couldn't be resolved, so there are no addresses to check.
+    /// Lookups are retried in the background until one succeeds
+    DnsFailure {
+        /// Why the last lookup failed
+        reason: String,
+    },
+}
+
+/// Everything known about a server from the last time it was checked
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct StatusEntry {
+    /// Last known state of the server
+    #[serde(flatten)]
+    pub(crate) status: ServerStatus,
+    /// When the server was last checked, in seconds since the unix epoch
+    pub(crate) last_checked: Option<u64>,
+    /// Why the last check failed, if it did
+    pub(crate) last_error: Option<String>,
+    /// When the server last responded, in seconds since the unix epoch
+    pub(crate) last_online: Option<u64>,
+    /// Round trip time of the last check in milliseconds, if the server responded.
+    /// Java servers are pinged separately so this doesn't include building their status
+    pub(crate) latency_ms: Option<u64>,
+    /// How many queries have failed for each edition since starting
+    #[serde(skip)]
+    pub(crate) query_failures: BTreeMap<Edition, u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{bedrock_response, server_info, status_entry},
+        render_page,
+    };
+    use minijinja::context;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_edition() {
+        assert_eq!(Edition::Java.default_port(), 25565);
+        assert_eq!(Edition::Bedrock.default_port(), 19132);
+        assert_eq!(Edition::Auto.default_port(), 25565);
+
+        assert_eq!(Edition::Bedrock.candidates(), [Edition::Bedrock]);
+        assert_eq!(
+            Edition::Auto.candidates(),
+            [Edition::Java, Edition::Bedrock, Edition::Legacy]
+        );
+    }
+
+    #[test]
+    fn test_render_dns_failure() {
+        let servers = [server_info("broken")];
+        let statuses = HashMap::from([(
+            "broken",
+            status_entry(ServerStatus::DnsFailure {
+                reason: "domain `<script>alert(1)</script>.example.com` does not exist (NXDOMAIN)"
+                    .to_string(),
+            }),
+        )]);
+
+        let page = render_page(
+            "all.html",
+            context! { servers => servers, statuses => statuses },
+        );
+        assert!(page.contains("DNS failure"));
+        assert!(page.contains("does not exist (NXDOMAIN)"));
+        // names in dns errors come from dns records, so can't add markup
+        assert!(page.contains("&lt;script&gt;alert(1)"));
+        assert!(!page.contains("<script>alert(1)"));
+        assert!(!page.contains("Offline"));
+    }
+
+    #[test]
+    fn test_render_bedrock() {
+        let Response::Bedrock(mut response) = bedrock_response() else {
+            unreachable!()
+        };
+        // values from the server can't add markup either
+        response.map = Some("<b>Bedrock level</b>".to_string());
+
+        let servers = [server_info("bedrock")];
+        let statuses = HashMap::from([(
+            "bedrock",
+            status_entry(ServerStatus::Online(Response::Bedrock(response))),
+        )]);
+
+        let page = render_page(
+            "all.html",
+            context! { servers => servers, statuses => statuses },
+        );
+        assert!(page.contains("3 / 10 players"));
+        assert!(page.contains("35 ms"));
+        assert!(page.contains("MCPE 1.20.40"));
+        assert!(page.contains("Creative"));
+        assert!(page.contains("&lt;b&gt;Bedrock level"));
+        assert!(!page.contains("<b>Bedrock level"));
+        assert!(page.contains("12345678901234567890"));
+
+        let page = render_page(
+            "single.html",
+            context! { server => servers[0], status => statuses["bedrock"] },
+        );
+        assert!(page.contains("MCPE 1.20.40"));
+        assert!(page.contains("Creative"));
+        assert!(page.contains("35 ms"));
+    }
+}